@@ -0,0 +1,5 @@
+-- Only one schema can be active at a time, it is the one used for validation
+
+ALTER TABLE schemas ADD COLUMN active BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE UNIQUE INDEX IF NOT EXISTS schemas_active_idx ON schemas (active) WHERE active = 1;
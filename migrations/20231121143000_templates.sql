@@ -0,0 +1,20 @@
+-- Policy templates with ?principal / ?resource slots and the links that fill them in
+
+CREATE TABLE IF NOT EXISTS templates
+(
+    id          TEXT PRIMARY KEY NOT NULL,
+    content     TEXT                NOT NULL,
+    created_ts      timestamp with time zone,
+    updated_ts   timestamp with time zone
+);
+
+CREATE TABLE IF NOT EXISTS template_links
+(
+    id          TEXT PRIMARY KEY NOT NULL,
+    template_id TEXT NOT NULL REFERENCES templates (id) ON DELETE CASCADE,
+    principal   TEXT,
+    resource    TEXT,
+    created_ts      timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS template_links_template_id_idx ON template_links (template_id);
@@ -0,0 +1,10 @@
+-- @id and @description annotations extracted from the policy content on write.
+-- namespace is taken from the policy's action scope and is '' for unqualified actions.
+
+ALTER TABLE policies ADD COLUMN annotation_id TEXT;
+ALTER TABLE policies ADD COLUMN description TEXT;
+ALTER TABLE policies ADD COLUMN namespace TEXT NOT NULL DEFAULT '';
+
+CREATE UNIQUE INDEX IF NOT EXISTS policies_namespace_annotation_id_idx
+    ON policies (namespace, annotation_id) WHERE annotation_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS policies_annotation_id_idx ON policies (annotation_id);
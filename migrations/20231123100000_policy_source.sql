@@ -0,0 +1,5 @@
+-- Policies created from the same multi-statement document share its source_id
+
+ALTER TABLE policies ADD COLUMN source_id TEXT;
+
+CREATE INDEX IF NOT EXISTS policies_source_id_idx ON policies (source_id);
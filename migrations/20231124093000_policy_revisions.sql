@@ -0,0 +1,15 @@
+-- Append-only history of every version of a policy's content
+
+CREATE TABLE IF NOT EXISTS policy_revisions
+(
+    policy_id   TEXT    NOT NULL REFERENCES policies (id) ON DELETE CASCADE,
+    revision    INTEGER NOT NULL,
+    content     TEXT    NOT NULL,
+    author      TEXT,
+    reason      TEXT,
+    created_ts      timestamp with time zone,
+    PRIMARY KEY (policy_id, revision)
+);
+
+INSERT INTO policy_revisions (policy_id, revision, content, reason, created_ts)
+SELECT id, 1, content, 'initial version', created_ts FROM policies;
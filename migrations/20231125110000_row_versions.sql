@@ -0,0 +1,4 @@
+-- Version counters exposed as ETags for optimistic concurrency on updates and deletes
+
+ALTER TABLE policies ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE entities ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
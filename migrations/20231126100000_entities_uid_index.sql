@@ -0,0 +1,3 @@
+-- Entities are addressed by their Cedar UID, so (etype, eid) has to be unique
+
+CREATE UNIQUE INDEX IF NOT EXISTS entities_etype_eid_idx ON entities (etype, eid);
@@ -0,0 +1,16 @@
+-- One row per parent listed in an entity's content, so the hierarchy can be walked in both
+-- directions without scanning the content JSON
+
+CREATE TABLE IF NOT EXISTS entity_parents
+(
+    entity_id     TEXT NOT NULL REFERENCES entities (id) ON DELETE CASCADE,
+    parent_etype  TEXT NOT NULL,
+    parent_eid    TEXT NOT NULL,
+    PRIMARY KEY (entity_id, parent_etype, parent_eid)
+);
+
+CREATE INDEX IF NOT EXISTS entity_parents_parent_idx ON entity_parents (parent_etype, parent_eid);
+
+INSERT OR IGNORE INTO entity_parents (entity_id, parent_etype, parent_eid)
+SELECT e.id, json_extract(p.value, '$.type'), json_extract(p.value, '$.id')
+FROM entities e, json_each(e.content, '$.parents') p;
@@ -0,0 +1,20 @@
+-- Revisions outlive their policy: deleting a policy appends a final revision instead of
+-- cascading to its history. SQLite cannot drop a foreign key, so the table is rebuilt.
+
+CREATE TABLE IF NOT EXISTS policy_revisions_new
+(
+    policy_id   TEXT    NOT NULL,
+    revision    INTEGER NOT NULL,
+    content     TEXT    NOT NULL,
+    author      TEXT,
+    reason      TEXT,
+    created_ts      timestamp with time zone,
+    PRIMARY KEY (policy_id, revision)
+);
+
+INSERT INTO policy_revisions_new (policy_id, revision, content, author, reason, created_ts)
+SELECT policy_id, revision, content, author, reason, created_ts FROM policy_revisions;
+
+DROP TABLE policy_revisions;
+
+ALTER TABLE policy_revisions_new RENAME TO policy_revisions;
@@ -1,89 +1,926 @@
-use std::error::Error;
-use std::{str::FromStr, time::Duration};
+use std::collections::HashMap;
+use std::str::FromStr;
 
-use cedar_policy::{Context, Entities, EntityUid, PolicySet, Request};
+use cedar_policy::{
+    ActionConstraint, Context, Diagnostics, Entities, EntityUid, Policy, PolicyId, PolicySet,
+    PrincipalConstraint, Request, ResourceConstraint, Schema, SlotId, Template, ValidationMode,
+    Validator,
+};
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::SqlitePool;
 
-use crate::core::{error::AuthorizationRequestError, structs::AuthorizationRequest};
+use super::cache::EntityCache;
+use super::schema::ActiveSchema;
+use crate::core::error::AuthorizationRequestError;
+use crate::core::structs::{
+    AuthorizationRequest, Explanation, PolicyEvaluationError, PolicyExplanation,
+};
 
 pub async fn prepare_cedar_request(
     authz_request: &AuthorizationRequest,
-) -> Result<Request, Box<dyn Error + Send + Sync>> {
+    schema: Option<&ActiveSchema>,
+) -> Result<Request, AuthorizationRequestError> {
     let principal = match EntityUid::from_str(&authz_request.principal) {
         Ok(p) => p,
-        Err(_) => return Err(Box::from(AuthorizationRequestError::InvalidPrincipal)),
+        Err(err) => return Err(AuthorizationRequestError::InvalidPrincipal(err.to_string())),
     };
 
     let action = match EntityUid::from_str(&authz_request.action) {
         Ok(a) => a,
-        Err(_) => return Err(Box::from(AuthorizationRequestError::InvalidAction)),
+        Err(err) => return Err(AuthorizationRequestError::InvalidAction(err.to_string())),
     };
 
     let resource = match EntityUid::from_str(&authz_request.resource) {
         Ok(r) => r,
-        Err(_) => return Err(Box::from(AuthorizationRequestError::InvalidResource)),
+        Err(err) => return Err(AuthorizationRequestError::InvalidResource(err.to_string())),
     };
 
-    let context = match &authz_request.context {
-        Some(c) => match Context::from_json_value(c.clone(), None) {
+    let context = match (&authz_request.context, schema) {
+        // With a schema the context is always checked, so a missing context is treated as empty
+        (c, Some(schema)) => {
+            let c = c
+                .clone()
+                .unwrap_or_else(|| Value::Object(Default::default()));
+            match Context::from_json_value(c.clone(), Some((schema, &action))) {
+                Ok(c) => c,
+                Err(err) => {
+                    let errors = schema.context_errors(&action, &c);
+                    let message = if errors.is_empty() {
+                        err.to_string()
+                    } else {
+                        errors.join("; ")
+                    };
+                    return Err(AuthorizationRequestError::InvalidContext(message));
+                }
+            }
+        }
+        (Some(c), None) => match Context::from_json_value(c.clone(), None) {
             Ok(c) => c,
-            Err(_err) => return Err(Box::from(AuthorizationRequestError::InvalidContext)),
+            Err(err) => return Err(AuthorizationRequestError::InvalidContext(err.to_string())),
         },
-        _ => Context::empty(),
+        (None, None) => Context::empty(),
     };
 
-    return Ok(Request::new(
+    Ok(Request::new(
         Some(principal),
         Some(action),
         Some(resource),
         context,
-    ));
+    ))
 }
 
 pub async fn fetch_entities(
-    _authz_request: &AuthorizationRequest,
-) -> Result<Entities, Box<dyn Error + Send + Sync>> {
-    // check if entities are present in authz_request
-    // if not present, fetch entities from sqlx
+    pool: &SqlitePool,
+    cache: &EntityCache,
+    authz_request: &AuthorizationRequest,
+    schema: Option<&Schema>,
+) -> Result<Entities, AuthorizationRequestError> {
+    authz_request.calculate_entities(pool, cache, schema).await
+}
 
-    let ents = r#"[
-                {
-                    "uid": {"type":"User","id":"alice"},
-                    "attrs": {
-                        "age":19,
-                        "ip_addr":{"__extn":{"fn":"ip", "arg":"10.0.1.101"}}
-                    },
-                    "parents": []
+pub async fn fetch_policies(pool: &SqlitePool) -> Result<PolicySet, AuthorizationRequestError> {
+    let rows: Vec<(String, String)> = sqlx::query_as("SELECT id, content FROM policies")
+        .fetch_all(pool)
+        .await?;
+
+    let mut policies = PolicySet::new();
+    for (id, content) in rows {
+        let added = match parse_policy(&id, &content) {
+            Ok(p) => policies.add(p).map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        };
+        if let Err(message) = added {
+            return Err(AuthorizationRequestError::InvalidPolicy(id, message));
+        }
+    }
+
+    let templates: Vec<(String, String)> = sqlx::query_as("SELECT id, content FROM templates")
+        .fetch_all(pool)
+        .await?;
+    for (id, content) in templates {
+        let added = match parse_template(&id, &content) {
+            Ok(t) => policies.add_template(t).map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        };
+        if let Err(message) = added {
+            return Err(AuthorizationRequestError::InvalidPolicy(id, message));
+        }
+    }
+
+    let links: Vec<(String, String, Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT id, template_id, principal, resource FROM template_links")
+            .fetch_all(pool)
+            .await?;
+    for (id, template_id, principal, resource) in links {
+        let linked = link_template(
+            &mut policies,
+            &template_id,
+            &id,
+            principal.as_deref(),
+            resource.as_deref(),
+        );
+        if let Err(message) = linked {
+            return Err(AuthorizationRequestError::InvalidPolicy(id, message));
+        }
+    }
+
+    Ok(policies)
+}
+
+/// Splits rows stored before policies were kept one statement per row. The first statement
+/// stays in the row; every other one gets a row of its own, and all of them share the
+/// original row's `source_id`. Returns the number of rows that were split.
+pub async fn split_legacy_policies(pool: &SqlitePool) -> Result<usize, AuthorizationRequestError> {
+    let rows: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT id, content, COALESCE(source_id, id) FROM policies")
+            .fetch_all(pool)
+            .await?;
+
+    let mut tr = pool.begin().await?;
+    let mut split = 0;
+    for (id, content, source_id) in rows {
+        // Rows that fail to parse are left for `fetch_policies` to report
+        let statements = match split_policies(&content) {
+            Ok(statements) if statements.len() > 1 => statements,
+            _ => continue,
+        };
+        log::warn!(
+            "splitting policy {} into {} single-statement policies",
+            id,
+            statements.len()
+        );
+        let now = Utc::now().to_rfc3339();
+
+        for (index, statement) in statements.iter().enumerate() {
+            let policy_id = match index {
+                0 => id.clone(),
+                _ => uuid::Uuid::new_v4().to_string(),
+            };
+            let annotations = match parse_policy(&policy_id, statement) {
+                Ok(policy) => policy_annotations(&policy),
+                Err(message) => {
+                    return Err(AuthorizationRequestError::InvalidPolicy(policy_id, message))
                 }
-            ]"#;
+            };
+
+            let query = if index == 0 {
+                "UPDATE policies SET content = $2, annotation_id = $3, description = $4,
+                 namespace = $5, source_id = $6, updated_ts = $7, version = version + 1
+                 WHERE id = $1"
+            } else {
+                "INSERT INTO policies (id, content, annotation_id, description, namespace,
+                 source_id, updated_ts, ttl, search_tags, created_ts)
+                 SELECT $1, $2, $3, $4, $5, $6, '', ttl, json('{}'), created_ts
+                 FROM policies WHERE id = $7"
+            };
+            sqlx::query(query)
+                .bind(&policy_id)
+                .bind(statement)
+                .bind(annotations.id)
+                .bind(annotations.description)
+                .bind(annotations.namespace)
+                .bind(&source_id)
+                // The kept row's `updated_ts`, or the row a new one copies ttl and created_ts from
+                .bind(if index == 0 { &now } else { &id })
+                .execute(&mut tr)
+                .await?;
+
+            sqlx::query(
+                "INSERT INTO policy_revisions (policy_id, revision, content, reason, created_ts)
+                 SELECT $1, COALESCE(MAX(revision), 0) + 1, $2, $3, $4
+                 FROM policy_revisions WHERE policy_id = $1",
+            )
+            .bind(&policy_id)
+            .bind(statement)
+            .bind(format!("split from policy {}", id))
+            .bind(&now)
+            .execute(&mut tr)
+            .await?;
+        }
+        split += 1;
+    }
+    tr.commit().await?;
+    Ok(split)
+}
+
+fn count_statements(content: &str) -> Result<usize, String> {
+    match PolicySet::from_str(content) {
+        Ok(p) => Ok(p.policies().count() + p.templates().count()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Splits a Cedar document into the source text of each statement, keeping any comments
+/// that precede a statement with it. A comment after the `;` on the same line, and any
+/// comments after the last statement, stay with the statement before them.
+pub fn split_policies(content: &str) -> Result<Vec<String>, String> {
+    let expected = count_statements(content)?;
+
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                let rest = &content[i + 1..];
+                let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+                let tail = line.trim_start();
+                let end = if tail.is_empty() || tail.starts_with("//") {
+                    i + 1 + line.len()
+                } else {
+                    i + 1
+                };
+                while matches!(chars.peek(), Some((j, _)) if *j < end) {
+                    chars.next();
+                }
+                statements.push(content[start..end].trim().to_string());
+                start = end;
+            }
+            _ => {}
+        }
+    }
+
+    // Only comments can follow the last statement, since the statements were counted
+    let trailing = content[start..].trim();
+    if !trailing.is_empty() {
+        if let Some(last) = statements.last_mut() {
+            last.push('\n');
+            last.push_str(trailing);
+        }
+    }
+
+    if statements.len() != expected {
+        return Err(format!(
+            "expected {} statements, found {}",
+            expected,
+            statements.len()
+        ));
+    }
+    Ok(statements)
+}
+
+/// Parses the content of a `policies` row, which must hold exactly one Cedar policy.
+pub fn parse_policy(id: &str, content: &str) -> Result<Policy, String> {
+    let statements = count_statements(content)?;
+    if statements != 1 {
+        return Err(format!("expected exactly one policy, found {}", statements));
+    }
+
+    Policy::parse(Some(id.to_string()), content).map_err(|err| err.to_string())
+}
 
-    return match Entities::from_json_str(ents, None) {
-        Ok(e) => Ok(e),
-        Err(_) => Err(Box::from(AuthorizationRequestError::InvalidEntities)),
+/// The `@id` and `@description` annotations of a policy, plus the namespace its `@id` has to
+/// be unique in, which is the namespace of the first action in its scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyAnnotations {
+    pub id: Option<String>,
+    pub description: Option<String>,
+    pub namespace: String,
+}
+
+pub fn policy_annotations(policy: &Policy) -> PolicyAnnotations {
+    let action = match policy.action_constraint() {
+        ActionConstraint::Eq(uid) => Some(uid),
+        ActionConstraint::In(uids) => uids.into_iter().next(),
+        ActionConstraint::Any => None,
     };
+
+    PolicyAnnotations {
+        id: policy.annotation("id").map(str::to_string),
+        description: policy.annotation("description").map(str::to_string),
+        namespace: action
+            .map(|uid| uid.type_name().namespace())
+            .unwrap_or_default(),
+    }
+}
+
+/// Ids of the policies, template links included, that name `uid` in their scope or conditions.
+pub fn policies_referencing(policies: &PolicySet, uid: &EntityUid) -> Vec<String> {
+    let literal = serde_json::json!({"type": uid.type_name().to_string(), "id": uid.id().as_ref()});
+    let mut ids: Vec<String> = policies
+        .policies()
+        .filter(|policy| {
+            let principal = match policy.principal_constraint() {
+                PrincipalConstraint::Eq(p) | PrincipalConstraint::In(p) => &p == uid,
+                PrincipalConstraint::Any => false,
+            };
+            let resource = match policy.resource_constraint() {
+                ResourceConstraint::Eq(r) | ResourceConstraint::In(r) => &r == uid,
+                ResourceConstraint::Any => false,
+            };
+            let action = match policy.action_constraint() {
+                ActionConstraint::Eq(a) => &a == uid,
+                ActionConstraint::In(actions) => actions.contains(uid),
+                ActionConstraint::Any => false,
+            };
+            // The JSON form of the conditions holds each entity literal as an `__entity`
+            // object, so neither lookalike names nor comments match
+            let condition = match policy.to_json() {
+                Ok(json) => names_entity(&json["conditions"], &literal),
+                Err(err) => {
+                    log::warn!("cannot inspect the conditions of {}: {}", policy.id(), err);
+                    false
+                }
+            };
+            principal || resource || action || condition
+        })
+        .map(|policy| policy.id().to_string())
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn names_entity(value: &Value, literal: &Value) -> bool {
+    match value {
+        Value::Object(map) => {
+            map.get("__entity") == Some(literal) || map.values().any(|v| names_entity(v, literal))
+        }
+        Value::Array(items) => items.iter().any(|v| names_entity(v, literal)),
+        _ => false,
+    }
+}
+
+/// Parses the content of a `templates` row, which must hold exactly one Cedar template with
+/// at least one `?principal` or `?resource` slot.
+pub fn parse_template(id: &str, content: &str) -> Result<Template, String> {
+    let statements = count_statements(content)?;
+    if statements != 1 {
+        return Err(format!(
+            "expected exactly one template, found {}",
+            statements
+        ));
+    }
+
+    let template = Template::parse(Some(id.to_string()), content).map_err(|err| err.to_string())?;
+    if template.slots().next().is_none() {
+        return Err("template has no ?principal or ?resource slot".to_string());
+    }
+    Ok(template)
+}
+
+/// Links the template `template_id` in `policies` as the new policy `link_id`, filling its
+/// slots with the given entity UIDs.
+pub fn link_template(
+    policies: &mut PolicySet,
+    template_id: &str,
+    link_id: &str,
+    principal: Option<&str>,
+    resource: Option<&str>,
+) -> Result<(), String> {
+    let mut values = HashMap::new();
+    if let Some(p) = principal {
+        let uid = EntityUid::from_str(p).map_err(|err| format!("invalid principal: {}", err))?;
+        values.insert(SlotId::principal(), uid);
+    }
+    if let Some(r) = resource {
+        let uid = EntityUid::from_str(r).map_err(|err| format!("invalid resource: {}", err))?;
+        values.insert(SlotId::resource(), uid);
+    }
+
+    let template_id = PolicyId::from_str(template_id).map_err(|err| err.to_string())?;
+    let link_id = PolicyId::from_str(link_id).map_err(|err| err.to_string())?;
+    policies
+        .link(template_id, link_id, values)
+        .map_err(|err| err.to_string())
+}
+
+/// Looks up the stored text, annotations and search_tags of every policy that determined a
+/// decision, and splits Cedar's evaluation errors by policy.
+pub async fn explain_decision(
+    pool: &SqlitePool,
+    policies: &PolicySet,
+    diagnostics: &Diagnostics,
+) -> Result<Explanation, AuthorizationRequestError> {
+    let mut reasons: Vec<&PolicyId> = diagnostics.reason().collect();
+    reasons.sort_by_key(|id| id.to_string());
+
+    let mut determining = Vec::new();
+    for id in reasons {
+        let row: Option<(String, Option<Value>)> =
+            sqlx::query_as("SELECT content, search_tags FROM policies WHERE id = $1")
+                .bind(id.to_string())
+                .fetch_optional(pool)
+                .await?;
+
+        let policy = policies.policy(id);
+        let annotations = policy
+            .map(|p| {
+                p.annotations()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let (content, search_tags) = match row {
+            Some((content, search_tags)) => (content, search_tags),
+            None => (policy.map(|p| p.to_string()).unwrap_or_default(), None),
+        };
+
+        determining.push(PolicyExplanation {
+            id: id.to_string(),
+            content,
+            annotations,
+            search_tags,
+        });
+    }
+
+    let mut errored: Vec<PolicyEvaluationError> = diagnostics
+        .errors()
+        .map(|err| evaluation_error(err.to_string()))
+        .collect();
+    errored.sort_by(|a, b| (&a.id, &a.error).cmp(&(&b.id, &b.error)));
+
+    Ok(Explanation {
+        determining,
+        errored,
+    })
 }
 
-pub async fn fetch_policies(
-    _authz_request: &AuthorizationRequest,
-) -> Result<PolicySet, Box<dyn Error + Send + Sync>> {
-    // fetch policies from sqlx
-
-    let s = r#"                       
-                        permit (
-                        principal == User::"alice",
-                        action == Action::"view",
-                        resource == Album::"trip"
-                        )
-                        when { principal.ip_addr.isIpv4() };
-
-                        permit (
-                            principal == User::"alice",
-                            action == Action::"update",
-                            resource == Album::"trip"
-                            )
-                            when { principal.ip_addr.isIpv4() };
-                        "#;
-    return match PolicySet::from_str(s) {
-        Ok(p) => Ok(p),
-        Err(_err) => Err(Box::from(AuthorizationRequestError::InvalidPolicies)),
+// Cedar only hands evaluation errors out as strings, so the policy id is recovered from the
+// message it formats them with
+fn evaluation_error(message: String) -> PolicyEvaluationError {
+    let parsed = message
+        .strip_prefix("error occurred while evaluating policy `")
+        .and_then(|rest| rest.split_once("`: "));
+
+    match parsed {
+        Some((id, error)) => PolicyEvaluationError {
+            id: Some(id.to_string()),
+            error: error.to_string(),
+        },
+        None => PolicyEvaluationError {
+            id: None,
+            error: message,
+        },
+    }
+}
+
+pub async fn fetch_active_schema(
+    pool: &SqlitePool,
+) -> Result<Option<ActiveSchema>, AuthorizationRequestError> {
+    let content: Option<Value> = sqlx::query_scalar("SELECT content FROM schemas WHERE active = 1")
+        .fetch_optional(pool)
+        .await?;
+
+    match content {
+        Some(c) => match Schema::from_json_value(c.clone()) {
+            Ok(schema) => Ok(Some(ActiveSchema { schema, json: c })),
+            Err(err) => Err(AuthorizationRequestError::InvalidSchema(err.to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Runs the Cedar validator over `policies`, returning every error with its source location.
+pub fn validate_policies(policies: &PolicySet, schema: &Schema) -> Result<(), Vec<String>> {
+    let validator = Validator::new(schema.clone());
+    let result = validator.validate(policies, ValidationMode::default());
+    if result.validation_passed() {
+        return Ok(());
+    }
+
+    Err(result.validation_errors().map(|e| e.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        explain_decision, fetch_entities, fetch_policies, link_template, parse_policy,
+        parse_template, policies_referencing, policy_annotations, split_legacy_policies,
+        split_policies, validate_policies,
+    };
+    use crate::cedar::cache::EntityCache;
+    use crate::core::error::AuthorizationRequestError;
+    use crate::core::structs::AuthorizationRequest;
+    use crate::utils::test_utils::{insert_entity, memory_pool};
+    use cedar_policy::{
+        Authorizer, Context, Entities, EntityUid, EvalResult, PolicyId, PolicySet, Request, Schema,
     };
+    use sqlx::SqlitePool;
+    use std::str::FromStr;
+
+    async fn insert_policy(pool: &SqlitePool, id: &str, content: &str) {
+        sqlx::query("INSERT INTO policies (id, ttl, content) VALUES ($1, 1, $2)")
+            .bind(id)
+            .bind(content)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetch_policies_should_use_row_id_as_policy_id() {
+        let pool = memory_pool().await;
+        insert_policy(
+            &pool,
+            "view-trip",
+            r#"permit(principal == User::"alice", action == Action::"view", resource);"#,
+        )
+        .await;
+
+        let policies = fetch_policies(&pool).await.unwrap();
+        let id = PolicyId::from_str("view-trip").unwrap();
+        assert!(policies.policy(&id).is_some());
+    }
+
+    #[tokio::test]
+    async fn fetch_policies_should_report_invalid_policy() {
+        let pool = memory_pool().await;
+        insert_policy(&pool, "broken", "permit(principal, action").await;
+
+        match fetch_policies(&pool).await {
+            Err(AuthorizationRequestError::InvalidPolicy(id, _)) => assert_eq!(id, "broken"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn split_legacy_policies_should_give_each_statement_a_row() {
+        let pool = memory_pool().await;
+        insert_policy(
+            &pool,
+            "legacy",
+            r#"permit(principal == User::"alice", action == Action::"view", resource);
+            @id("bob-edit")
+            permit(principal == User::"bob", action == Action::"edit", resource);"#,
+        )
+        .await;
+        assert!(fetch_policies(&pool).await.is_err());
+
+        assert_eq!(split_legacy_policies(&pool).await.unwrap(), 1);
+        assert_eq!(split_legacy_policies(&pool).await.unwrap(), 0);
+
+        let rows: Vec<(String, Option<String>, String)> = sqlx::query_as(
+            "SELECT id, annotation_id, source_id FROM policies ORDER BY annotation_id NULLS FIRST",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, "legacy");
+        assert_eq!(rows[1].1.as_deref(), Some("bob-edit"));
+        assert!(rows.iter().all(|row| row.2 == "legacy"));
+
+        let policies = fetch_policies(&pool).await.unwrap();
+        assert_eq!(policies.policies().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn fetch_policies_should_include_template_links() {
+        let pool = memory_pool().await;
+        sqlx::query("INSERT INTO templates (id, content) VALUES ($1, $2)")
+            .bind("owner-view")
+            .bind(r#"permit(principal == ?principal, action == Action::"view", resource == ?resource);"#)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO template_links (id, template_id, principal, resource) VALUES ($1, $2, $3, $4)")
+            .bind("alice-trip")
+            .bind("owner-view")
+            .bind(r#"User::"alice""#)
+            .bind(r#"Trip::"paris""#)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let policies = fetch_policies(&pool).await.unwrap();
+        let id = PolicyId::from_str("alice-trip").unwrap();
+        let linked = policies.policy(&id).unwrap();
+        assert_eq!(linked.template_id().unwrap().to_string(), "owner-view");
+    }
+
+    #[tokio::test]
+    async fn explain_decision_should_describe_policies() {
+        let pool = memory_pool().await;
+        insert_policy(
+            &pool,
+            "view-trip",
+            r#"@description("anyone may view")
+permit(principal, action == Action::"view", resource);"#,
+        )
+        .await;
+        insert_policy(
+            &pool,
+            "adults",
+            r#"forbid(principal, action, resource) when { principal.age < 18 };"#,
+        )
+        .await;
+
+        let policies = fetch_policies(&pool).await.unwrap();
+        let request = Request::new(
+            Some(EntityUid::from_str(r#"User::"alice""#).unwrap()),
+            Some(EntityUid::from_str(r#"Action::"view""#).unwrap()),
+            Some(EntityUid::from_str(r#"Trip::"paris""#).unwrap()),
+            Context::empty(),
+        );
+        let r = Authorizer::new().is_authorized(&request, &policies, &Entities::empty());
+
+        let explanation = explain_decision(&pool, &policies, r.diagnostics())
+            .await
+            .unwrap();
+        assert_eq!(explanation.determining.len(), 1);
+        let view = &explanation.determining[0];
+        assert_eq!(view.id, "view-trip");
+        assert!(view.content.starts_with("@description"));
+        assert_eq!(view.annotations["description"], "anyone may view");
+        assert_eq!(explanation.errored.len(), 1);
+        assert_eq!(explanation.errored[0].id.as_deref(), Some("adults"));
+    }
+
+    #[test]
+    fn policies_referencing_should_check_scope_and_conditions() {
+        let mut policies = PolicySet::new();
+        let statements = [
+            (
+                "scope",
+                r#"permit(principal == User::"bob", action, resource);"#,
+            ),
+            (
+                "condition",
+                r#"permit(principal, action, resource) when { resource.owner == User::"bob" };"#,
+            ),
+            (
+                "other",
+                r#"permit(principal == User::"alice", action, resource);"#,
+            ),
+            (
+                "set",
+                r#"permit(principal, action, resource) when { principal in [Group::"a", User::"bob"] };"#,
+            ),
+            (
+                "lookalike",
+                r#"permit(principal == Other::User::"bob", action, resource)
+                   when { resource.owner == Other::User::"bob" && resource.note == "User::\"bob\"" };
+                   // User::"bob""#,
+            ),
+        ];
+        for (id, content) in statements {
+            policies.add(parse_policy(id, content).unwrap()).unwrap();
+        }
+        let template = parse_template(
+            "owned",
+            r#"permit(principal == ?principal, action, resource) when { resource.owner == User::"bob" };"#,
+        )
+        .unwrap();
+        policies.add_template(template).unwrap();
+        link_template(
+            &mut policies,
+            "owned",
+            "link",
+            Some(r#"User::"carol""#),
+            None,
+        )
+        .unwrap();
+
+        let bob = EntityUid::from_str(r#"User::"bob""#).unwrap();
+        assert_eq!(
+            policies_referencing(&policies, &bob),
+            vec![
+                "condition".to_string(),
+                "link".to_string(),
+                "scope".to_string(),
+                "set".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn policy_annotations_should_use_action_namespace() {
+        let policy = parse_policy(
+            "p1",
+            r#"@id("view-own")
+@description("owners may view their photos")
+permit(principal, action in [PhotoApp::Action::"view"], resource);"#,
+        )
+        .unwrap();
+
+        let annotations = policy_annotations(&policy);
+        assert_eq!(annotations.id.as_deref(), Some("view-own"));
+        assert_eq!(
+            annotations.description.as_deref(),
+            Some("owners may view their photos")
+        );
+        assert_eq!(annotations.namespace, "PhotoApp");
+    }
+
+    #[test]
+    fn split_policies_should_keep_each_statement() {
+        let document = r#"// staff can view
+permit(principal in Group::"staff", action == Action::"view", resource); // c1
+forbid(principal, action, resource) when { resource.name == "a;b" }; permit(principal, action, resource);
+// end of document
+"#;
+
+        let statements = split_policies(document).unwrap();
+        assert_eq!(
+            statements,
+            vec![
+                r#"// staff can view
+permit(principal in Group::"staff", action == Action::"view", resource); // c1"#,
+                r#"forbid(principal, action, resource) when { resource.name == "a;b" };"#,
+                r#"permit(principal, action, resource);
+// end of document"#,
+            ]
+        );
+        for (index, statement) in statements.iter().enumerate() {
+            assert!(parse_policy(&index.to_string(), statement).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_entities_should_include_ancestors() {
+        let pool = memory_pool().await;
+        insert_entity(
+            &pool,
+            serde_json::json!({
+                "uid": {"type": "User", "id": "alice"},
+                "attrs": {"age": 19},
+                "parents": [{"type": "Group", "id": "admins"}]
+            }),
+        )
+        .await;
+        insert_entity(
+            &pool,
+            serde_json::json!({
+                "uid": {"type": "Group", "id": "admins"},
+                "attrs": {},
+                "parents": [{"type": "Group", "id": "staff"}]
+            }),
+        )
+        .await;
+        insert_entity(
+            &pool,
+            serde_json::json!({
+                "uid": {"type": "Group", "id": "staff"},
+                "attrs": {},
+                "parents": []
+            }),
+        )
+        .await;
+
+        let authz = AuthorizationRequest {
+            principal: r#"User::"alice""#.to_string(),
+            action: r#"Action::"view""#.to_string(),
+            resource: r#"Album::"trip""#.to_string(),
+            context: None,
+            entities: None,
+        };
+        let entities = fetch_entities(&pool, &EntityCache::default(), &authz, None)
+            .await
+            .unwrap();
+
+        let alice = EntityUid::from_str(r#"User::"alice""#).unwrap();
+        let staff = EntityUid::from_str(r#"Group::"staff""#).unwrap();
+        assert!(entities.is_ancestor_of(&staff, &alice));
+        assert_eq!(entities.iter().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn fetch_entities_should_prefer_request_entities() {
+        let pool = memory_pool().await;
+        insert_entity(
+            &pool,
+            serde_json::json!({
+                "uid": {"type": "User", "id": "alice"},
+                "attrs": {},
+                "parents": [{"type": "Group", "id": "admins"}]
+            }),
+        )
+        .await;
+        insert_entity(
+            &pool,
+            serde_json::json!({
+                "uid": {"type": "Folder", "id": "shared"},
+                "attrs": {},
+                "parents": [{"type": "Folder", "id": "root"}]
+            }),
+        )
+        .await;
+
+        let authz = AuthorizationRequest {
+            principal: r#"User::"alice""#.to_string(),
+            action: r#"Action::"create""#.to_string(),
+            resource: r#"Document::"draft""#.to_string(),
+            context: None,
+            entities: Some(serde_json::json!([
+                {
+                    "uid": {"type": "User", "id": "alice"},
+                    "attrs": {},
+                    "parents": []
+                },
+                {
+                    "uid": {"type": "Document", "id": "draft"},
+                    "attrs": {},
+                    "parents": [{"type": "Folder", "id": "shared"}]
+                }
+            ])),
+        };
+        let entities = fetch_entities(&pool, &EntityCache::default(), &authz, None)
+            .await
+            .unwrap();
+
+        let alice = EntityUid::from_str(r#"User::"alice""#).unwrap();
+        let admins = EntityUid::from_str(r#"Group::"admins""#).unwrap();
+        let draft = EntityUid::from_str(r#"Document::"draft""#).unwrap();
+        let root = EntityUid::from_str(r#"Folder::"root""#).unwrap();
+        assert!(!entities.is_ancestor_of(&admins, &alice));
+        assert!(entities.is_ancestor_of(&root, &draft));
+    }
+
+    #[test]
+    fn validate_policies_should_report_location() {
+        let schema = Schema::from_json_value(serde_json::json!({
+            "": {
+                "entityTypes": {"User": {}, "Album": {}},
+                "actions": {
+                    "view": {"appliesTo": {"principalTypes": ["User"], "resourceTypes": ["Album"]}}
+                }
+            }
+        }))
+        .unwrap();
+
+        assert!(parse_policy(
+            "two",
+            "permit(principal, action, resource); forbid(principal, action, resource);"
+        )
+        .is_err());
+
+        let policy = parse_policy(
+            "adults",
+            r#"permit(principal, action == Action::"view", resource) when { principal.age > 18 };"#,
+        )
+        .unwrap();
+        let policies = PolicySet::from_policies([policy]).unwrap();
+        let errors = validate_policies(&policies, &schema).unwrap_err();
+        assert!(!errors.is_empty());
+        assert!(errors
+            .iter()
+            .all(|e| e.starts_with("Validation error on policy adults at offset")));
+    }
+
+    #[tokio::test]
+    async fn fetch_entities_should_parse_attributes_from_schema() {
+        let pool = memory_pool().await;
+        insert_entity(
+            &pool,
+            serde_json::json!({
+                "uid": {"type": "User", "id": "alice"},
+                "attrs": {"ip_addr": "10.0.1.101"},
+                "parents": []
+            }),
+        )
+        .await;
+        let schema = Schema::from_json_value(serde_json::json!({
+            "": {
+                "entityTypes": {
+                    "User": {
+                        "shape": {
+                            "type": "Record",
+                            "attributes": {"ip_addr": {"type": "Extension", "name": "ipaddr"}}
+                        }
+                    }
+                },
+                "actions": {}
+            }
+        }))
+        .unwrap();
+
+        let authz = AuthorizationRequest {
+            principal: r#"User::"alice""#.to_string(),
+            action: r#"Action::"view""#.to_string(),
+            resource: r#"User::"alice""#.to_string(),
+            context: None,
+            entities: None,
+        };
+        let entities = fetch_entities(&pool, &EntityCache::default(), &authz, Some(&schema))
+            .await
+            .unwrap();
+
+        let alice = EntityUid::from_str(r#"User::"alice""#).unwrap();
+        let ip = entities
+            .get(&alice)
+            .unwrap()
+            .attr("ip_addr")
+            .unwrap()
+            .unwrap();
+        assert_eq!(ip, EvalResult::ExtensionValue("10.0.1.101/32".to_string()));
+    }
 }
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use cedar_policy::PolicySet;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+use super::api::{fetch_active_schema, fetch_policies};
+use super::schema::ActiveSchema;
+use crate::core::db::EntityKey;
+use crate::core::error::AuthorizationRequestError;
+
+/// The parsed policy set that authorization runs against, swapped out whole whenever
+/// policies or templates are written. A stored policy that fails to parse is kept as the
+/// cached error, so authorization reports it until the policy is fixed or removed.
+pub struct PolicyCache {
+    current: ArcSwap<Result<Arc<PolicySet>, (String, String)>>,
+    // Serializes reloads so an older snapshot of the table can't overwrite a newer one
+    reloading: tokio::sync::Mutex<()>,
+}
+
+/// The policy set, or the id and parse error of the stored policy that prevented building it.
+async fn load_policies(
+    pool: &SqlitePool,
+) -> Result<Result<Arc<PolicySet>, (String, String)>, AuthorizationRequestError> {
+    match fetch_policies(pool).await {
+        Ok(policies) => Ok(Ok(Arc::new(policies))),
+        Err(AuthorizationRequestError::InvalidPolicy(id, message)) => {
+            log::error!("stored policy {} is invalid: {}", id, message);
+            Ok(Err((id, message)))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+impl PolicyCache {
+    pub async fn load(pool: &SqlitePool) -> Result<Self, AuthorizationRequestError> {
+        let policies = load_policies(pool).await?;
+        Ok(PolicyCache {
+            current: ArcSwap::from_pointee(policies),
+            reloading: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn get(&self) -> Result<Arc<PolicySet>, AuthorizationRequestError> {
+        match &**self.current.load() {
+            Ok(policies) => Ok(policies.clone()),
+            Err((id, message)) => Err(AuthorizationRequestError::InvalidPolicy(
+                id.clone(),
+                message.clone(),
+            )),
+        }
+    }
+
+    /// Re-reads every policy, template and link and replaces the cached set. If the database
+    /// can't be read the previous set stays in place.
+    pub async fn reload(&self, pool: &SqlitePool) -> Result<(), AuthorizationRequestError> {
+        let _guard = self.reloading.lock().await;
+        let policies = load_policies(pool).await?;
+        self.current.store(Arc::new(policies));
+        Ok(())
+    }
+
+    /// Reloads after a committed write. The write has already succeeded, so a failure is only
+    /// logged.
+    pub async fn refresh(&self, pool: &SqlitePool) {
+        if let Err(err) = self.reload(pool).await {
+            log::error!("failed to reload the policy cache: {}", err);
+        }
+    }
+}
+
+/// The parsed active schema, replaced whenever schemas are written or activated.
+pub struct SchemaCache {
+    current: ArcSwapOption<ActiveSchema>,
+    reloading: tokio::sync::Mutex<()>,
+}
+
+impl SchemaCache {
+    pub async fn load(pool: &SqlitePool) -> Result<Self, AuthorizationRequestError> {
+        let schema = fetch_active_schema(pool).await?;
+        Ok(SchemaCache {
+            current: ArcSwapOption::from(schema.map(Arc::new)),
+            reloading: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn get(&self) -> Option<Arc<ActiveSchema>> {
+        self.current.load_full()
+    }
+
+    /// Re-reads the active schema. On error the previous schema stays in place.
+    pub async fn reload(&self, pool: &SqlitePool) -> Result<(), AuthorizationRequestError> {
+        let _guard = self.reloading.lock().await;
+        let schema = fetch_active_schema(pool).await?;
+        self.current.store(schema.map(Arc::new));
+        Ok(())
+    }
+
+    /// Reloads after a committed write, logging a failure like `PolicyCache::refresh`.
+    pub async fn refresh(&self, pool: &SqlitePool) {
+        if let Err(err) = self.reload(pool).await {
+            log::error!("failed to reload the schema cache: {}", err);
+        }
+    }
+}
+
+/// Stored entity contents keyed by `(etype, eid)`, each kept for the `ttl` seconds of its
+/// row. Rows without a ttl are never cached.
+#[derive(Default)]
+pub struct EntityCache {
+    entries: Mutex<HashMap<EntityKey, CachedEntity>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct CachedEntity {
+    content: Value,
+    expires: Instant,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct EntityCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl EntityCache {
+    pub fn get(&self, key: &EntityKey) -> Option<Value> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let content = match entries.get(key) {
+            Some(cached) if cached.expires > Instant::now() => Some(cached.content.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        };
+
+        let counter = match content {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        content
+    }
+
+    pub fn insert(&self, key: EntityKey, content: Value, ttl: Option<u32>) {
+        let ttl = match ttl {
+            Some(ttl) if ttl > 0 => Duration::from_secs(ttl.into()),
+            _ => return,
+        };
+
+        let cached = CachedEntity {
+            content,
+            expires: Instant::now() + ttl,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.insert(key, cached);
+    }
+
+    pub fn evict(&self, key: &EntityKey) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.remove(key);
+    }
+
+    pub fn stats(&self) -> EntityCacheStats {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        entries.retain(|_, cached| cached.expires > now);
+
+        EntityCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: entries.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntityCache, PolicyCache};
+    use crate::core::error::AuthorizationRequestError;
+    use cedar_policy::PolicyId;
+    use crate::utils::test_utils::memory_pool;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn reload_should_replace_cached_policies() {
+        let pool = memory_pool().await;
+
+        let cache = PolicyCache::load(&pool).await.unwrap();
+        let before = cache.get().unwrap();
+        assert_eq!(before.policies().count(), 0);
+
+        sqlx::query("INSERT INTO policies (id, ttl, content) VALUES ($1, 1, $2)")
+            .bind("view-trip")
+            .bind(r#"permit(principal, action == Action::"view", resource);"#)
+            .execute(&pool)
+            .await
+            .unwrap();
+        cache.reload(&pool).await.unwrap();
+
+        let id = PolicyId::from_str("view-trip").unwrap();
+        assert!(cache.get().unwrap().policy(&id).is_some());
+        assert_eq!(before.policies().count(), 0);
+
+        sqlx::query("INSERT INTO policies (id, ttl, content) VALUES ('broken', 1, 'garbage')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let cache = PolicyCache::load(&pool).await.unwrap();
+        match cache.get() {
+            Err(AuthorizationRequestError::InvalidPolicy(id, _)) => assert_eq!(id, "broken"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn entity_cache_should_honor_ttl() {
+        let cache = EntityCache::default();
+        let alice = ("User".to_string(), "alice".to_string());
+        let bob = ("User".to_string(), "bob".to_string());
+
+        cache.insert(alice.clone(), serde_json::json!({"attrs": {}}), Some(60));
+        cache.insert(bob.clone(), serde_json::json!({"attrs": {}}), None);
+        assert!(cache.get(&alice).is_some());
+        assert!(cache.get(&bob).is_none());
+
+        cache.evict(&alice);
+        assert!(cache.get(&alice).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 0));
+    }
+}
@@ -1 +1,3 @@
 pub mod api;
+pub mod cache;
+pub mod schema;
@@ -0,0 +1,186 @@
+use std::ops::Deref;
+
+use cedar_policy::{EntityUid, Schema};
+use serde_json::Value;
+
+/// The active schema along with the JSON it was parsed from, which is used to explain
+/// context type errors that Cedar reports without naming the attribute.
+pub struct ActiveSchema {
+    pub schema: Schema,
+    pub json: Value,
+}
+
+impl Deref for ActiveSchema {
+    type Target = Schema;
+
+    fn deref(&self) -> &Schema {
+        &self.schema
+    }
+}
+
+impl ActiveSchema {
+    /// Lists every context attribute that is missing, undeclared or mistyped according to the
+    /// context type `action` declares.
+    pub fn context_errors(&self, action: &EntityUid, context: &Value) -> Vec<String> {
+        let namespace = &self.json[action.type_name().namespace()];
+        let declared = &namespace["actions"][action.id().as_ref()];
+        if declared.is_null() {
+            return vec![format!("action {} is not declared in the schema", action)];
+        }
+
+        let mut errors = Vec::new();
+        let context_type = &declared["appliesTo"]["context"];
+        if context_type.is_null() {
+            check_record("", &Value::Null, context, namespace, &mut errors);
+        } else {
+            check_value("context", context_type, context, namespace, &mut errors);
+        }
+        errors
+    }
+}
+
+fn check_value(path: &str, ty: &Value, value: &Value, namespace: &Value, errors: &mut Vec<String>) {
+    let type_name = ty["type"].as_str().unwrap_or_default();
+    let matches = match type_name {
+        "Long" => value.is_i64(),
+        "String" => value.is_string(),
+        "Boolean" => value.is_boolean(),
+        "Entity" => value.is_object(),
+        "Extension" => value.is_string() || value.get("__extn").is_some(),
+        "Set" => match value.as_array() {
+            Some(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let element_path = format!("{}[{}]", path, i);
+                    check_value(&element_path, &ty["element"], element, namespace, errors);
+                }
+                true
+            }
+            None => false,
+        },
+        "Record" => {
+            if value.is_object() {
+                let prefix = if path == "context" { "" } else { path };
+                check_record(prefix, ty, value, namespace, errors);
+            }
+            value.is_object()
+        }
+        common => {
+            let common_type = &namespace["commonTypes"][common];
+            if !common_type.is_null() {
+                check_value(path, common_type, value, namespace, errors);
+            }
+            true
+        }
+    };
+
+    if !matches {
+        errors.push(format!(
+            "attribute `{}` should be {}, but is {}",
+            path,
+            type_name,
+            json_kind(value)
+        ));
+    }
+}
+
+fn check_record(
+    prefix: &str,
+    ty: &Value,
+    value: &Value,
+    namespace: &Value,
+    errors: &mut Vec<String>,
+) {
+    let attributes = ty["attributes"].as_object();
+    let path = |name: &str| match prefix {
+        "" => name.to_string(),
+        _ => format!("{}.{}", prefix, name),
+    };
+
+    for (name, attr_ty) in attributes.into_iter().flatten() {
+        match value.get(name) {
+            Some(v) => check_value(&path(name), attr_ty, v, namespace, errors),
+            None if attr_ty["required"].as_bool().unwrap_or(true) => errors.push(format!(
+                "attribute `{}` of type {} is missing",
+                path(name),
+                attr_ty["type"].as_str().unwrap_or_default()
+            )),
+            None => {}
+        }
+    }
+
+    if ty["additionalAttributes"].as_bool().unwrap_or(false) {
+        return;
+    }
+    for name in value.as_object().into_iter().flat_map(|o| o.keys()) {
+        if !attributes.is_some_and(|a| a.contains_key(name)) {
+            errors.push(format!(
+                "attribute `{}` is not declared in the schema",
+                path(name)
+            ));
+        }
+    }
+}
+
+fn json_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "a set",
+        Value::Object(_) => "a record",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActiveSchema;
+    use cedar_policy::{EntityUid, Schema};
+    use std::str::FromStr;
+
+    #[test]
+    fn context_errors_should_name_attributes() {
+        let json = serde_json::json!({
+            "": {
+                "entityTypes": {},
+                "actions": {
+                    "view": {
+                        "appliesTo": {
+                            "context": {
+                                "type": "Record",
+                                "attributes": {
+                                    "mfa": {"type": "Boolean"},
+                                    "source": {
+                                        "type": "Record",
+                                        "attributes": {
+                                            "ip": {"type": "Extension", "name": "ipaddr"}
+                                        }
+                                    },
+                                    "reason": {"type": "String", "required": false}
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        let schema = ActiveSchema {
+            schema: Schema::from_json_value(json.clone()).unwrap(),
+            json,
+        };
+        let action = EntityUid::from_str(r#"Action::"view""#).unwrap();
+
+        let errors = schema.context_errors(
+            &action,
+            &serde_json::json!({"source": {"ip": 10}, "extra": 1}),
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "attribute `mfa` of type Boolean is missing",
+                "attribute `source.ip` should be Extension, but is a number",
+                "attribute `extra` is not declared in the schema",
+            ]
+        );
+    }
+}
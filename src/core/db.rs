@@ -1,15 +1,418 @@
-use cedar_policy::{Entities, Entity};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
 
+use cedar_policy::{Entities, EntityUid, Schema};
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+use super::error::AuthorizationRequestError;
 use super::structs::AuthorizationRequest;
+use crate::cedar::cache::EntityCache;
+use crate::dto::entities::{Hierarchy, HierarchyEntity, Parent, UID};
+
+pub type EntityKey = (String, String);
 
 impl AuthorizationRequest {
-    async fn calculate_entities(&self) -> Entities {
-        // Grab attributes and roles for principal, and resource. This must be done concurrently.
+    /// Builds the entity store for this request from the `entities` table, overlaid with any
+    /// entities supplied inline in the request. An inline entity replaces the stored entity
+    /// with the same UID as a whole (attributes and parents), and stored ancestors are only
+    /// followed from the inline entity's own parents.
+    pub async fn calculate_entities(
+        &self,
+        pool: &SqlitePool,
+        cache: &EntityCache,
+        schema: Option<&Schema>,
+    ) -> Result<Entities, AuthorizationRequestError> {
+        let inline = self.inline_entities(schema)?;
+        let skip = inline.iter().map(|e| uid_key(&e.uid())).collect();
+
+        let stored = fetch_entity_contents(pool, cache, self.entity_roots(&inline)?, skip).await?;
+        let entities = parse_entities(&stored, schema)?;
+
+        if inline.iter().next().is_none() {
+            return Ok(entities);
+        }
+        self.merge_entities(&stored, &inline, schema)
+    }
+
+    pub fn inline_entities(
+        &self,
+        schema: Option<&Schema>,
+    ) -> Result<Entities, AuthorizationRequestError> {
+        match &self.entities {
+            Some(json) => Entities::from_json_value(json.clone(), schema)
+                .map_err(|err| AuthorizationRequestError::InvalidRequestEntities(err.to_string())),
+            None => Ok(Entities::empty()),
+        }
+    }
+
+    /// Stored entities this request is resolved from: principal, action, resource and the
+    /// parents of any inline entities.
+    pub fn entity_roots(
+        &self,
+        inline: &Entities,
+    ) -> Result<Vec<EntityKey>, AuthorizationRequestError> {
+        let mut roots = vec![
+            entity_key(&self.principal, AuthorizationRequestError::InvalidPrincipal)?,
+            entity_key(&self.action, AuthorizationRequestError::InvalidAction)?,
+            entity_key(&self.resource, AuthorizationRequestError::InvalidResource)?,
+        ];
+        for entity in inline.iter() {
+            if let Some(ancestors) = inline.ancestors(&entity.uid()) {
+                roots.extend(ancestors.map(uid_key));
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Overlays the inline entities on `stored`, dropping every stored entity that is
+    /// redefined inline.
+    pub fn merge_entities(
+        &self,
+        stored: &[Value],
+        inline: &Entities,
+        schema: Option<&Schema>,
+    ) -> Result<Entities, AuthorizationRequestError> {
+        let overridden: HashSet<EntityKey> = inline.iter().map(|e| uid_key(&e.uid())).collect();
+        let mut merged: Vec<Value> = stored
+            .iter()
+            .filter(|content| !overridden.contains(&content_key(content)))
+            .cloned()
+            .collect();
+        if let Some(Value::Array(json)) = &self.entities {
+            merged.extend(json.iter().cloned());
+        }
+
+        match Entities::from_json_value(Value::Array(merged), schema) {
+            Ok(e) => Ok(e),
+            Err(err) => Err(AuthorizationRequestError::ConflictingEntities(
+                err.to_string(),
+            )),
+        }
+    }
+}
+
+/// Walks from `roots` up through every parent, serving entities from `cache` where it can and
+/// caching rows read from the table for their ttl. Entities in `skip` are not fetched.
+///
+/// The walk goes one level at a time; the entities of a level that are not cached are
+/// fetched together with all of their stored ancestors in a single query.
+pub async fn fetch_entity_contents(
+    pool: &SqlitePool,
+    cache: &EntityCache,
+    roots: Vec<EntityKey>,
+    skip: HashSet<EntityKey>,
+) -> Result<Vec<Value>, AuthorizationRequestError> {
+    let mut seen = skip;
+    let mut pending = roots;
+    let mut contents: Vec<Value> = Vec::new();
+
+    while !pending.is_empty() {
+        let mut found = Vec::new();
+        let mut misses = Vec::new();
+        let visited: Vec<EntityKey> = seen.iter().cloned().collect();
+        for key in pending.drain(..) {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            match cache.get(&key) {
+                Some(content) => found.push(content),
+                None => misses.push(key),
+            }
+        }
+
+        if !misses.is_empty() {
+            for (content, ttl) in fetch_ancestor_contents(pool, &misses, &visited).await? {
+                let key = content_key(&content);
+                if misses.contains(&key) || seen.insert(key.clone()) {
+                    cache.insert(key, content.clone(), ttl);
+                    found.push(content);
+                }
+            }
+        }
+
+        for content in found {
+            let parents: Vec<Parent> =
+                serde_json::from_value(content.get("parents").cloned().unwrap_or_default())
+                    .unwrap_or_default();
+            pending.extend(
+                parents
+                    .into_iter()
+                    .map(|p| (p.r#type, p.id))
+                    .filter(|key| !seen.contains(key)),
+            );
+            contents.push(content);
+        }
+    }
+
+    Ok(contents)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HierarchyDirection {
+    /// Towards the parents of an entity
+    Ancestors,
+    /// Towards the entities that list an entity as a parent
+    Descendants,
+}
+
+/// An entity reached by `walk_hierarchy`, and the entity it was reached from.
+#[derive(sqlx::FromRow)]
+struct WalkStep {
+    etype: String,
+    eid: String,
+    depth: u32,
+    from_etype: Option<String>,
+    from_eid: Option<String>,
+}
+
+/// Walks the parent graph stored in `entity_parents` from `root` for at most `max_depth`
+/// levels, in one recursive query. Every entity is reported once, at the shortest distance it
+/// was reached by, ordered by that distance. Parents that are referenced but not stored are
+/// reported without being followed.
+pub async fn walk_hierarchy(
+    pool: &SqlitePool,
+    root: &EntityKey,
+    direction: HierarchyDirection,
+    max_depth: u32,
+) -> Result<Hierarchy, sqlx::Error> {
+    let step = match direction {
+        HierarchyDirection::Ancestors => {
+            "SELECT ep.parent_etype, ep.parent_eid, w.depth + 1, w.etype, w.eid
+             FROM walk w
+             JOIN entities e ON e.etype = w.etype AND e.eid = w.eid
+             JOIN entity_parents ep ON ep.entity_id = e.id"
+        }
+        HierarchyDirection::Descendants => {
+            "SELECT e.etype, e.eid, w.depth + 1, w.etype, w.eid
+             FROM walk w
+             JOIN entity_parents ep ON ep.parent_etype = w.etype AND ep.parent_eid = w.eid
+             JOIN entities e ON e.id = ep.entity_id"
+        }
+    };
+    // One level past `max_depth` is walked to tell whether the walk was cut short
+    let query = format!(
+        "WITH RECURSIVE walk(etype, eid, depth, from_etype, from_eid) AS (
+            SELECT $1, $2, 0, NULL, NULL
+            UNION
+            {} WHERE w.depth <= $3
+        )
+        SELECT etype, eid, depth, from_etype, from_eid FROM walk",
+        step
+    );
+    let rows: Vec<WalkStep> = sqlx::query_as(&query)
+        .bind(&root.0)
+        .bind(&root.1)
+        .bind(max_depth)
+        .fetch_all(pool)
+        .await?;
+
+    let mut depths: HashMap<EntityKey, u32> = HashMap::new();
+    for step in &rows {
+        let known = depths
+            .entry((step.etype.clone(), step.eid.clone()))
+            .or_insert(step.depth);
+        *known = (*known).min(step.depth);
+    }
+
+    let mut edges: HashMap<EntityKey, BTreeSet<EntityKey>> = HashMap::new();
+    for step in rows {
+        let to = (step.etype, step.eid);
+        if let (Some(from_etype), Some(from_eid)) = (step.from_etype, step.from_eid) {
+            if depths[&to] <= max_depth {
+                edges.entry((from_etype, from_eid)).or_default().insert(to);
+            }
+        }
+    }
+
+    let mut entities: Vec<(u32, EntityKey)> = depths
+        .iter()
+        .filter(|(key, depth)| *key != root && **depth <= max_depth)
+        .map(|(key, depth)| (*depth, key.clone()))
+        .collect();
+    entities.sort();
+
+    Ok(Hierarchy {
+        entities: entities
+            .into_iter()
+            .map(|(depth, (r#type, id))| HierarchyEntity {
+                uid: UID { r#type, id },
+                depth,
+            })
+            .collect(),
+        truncated: depths.values().any(|depth| *depth > max_depth),
+        cycle: has_cycle(root, &edges),
+    })
+}
+
+/// Whether a depth-first walk from `root` meets an edge back to an entity on its current
+/// path.
+fn has_cycle(root: &EntityKey, edges: &HashMap<EntityKey, BTreeSet<EntityKey>>) -> bool {
+    let none = BTreeSet::new();
+    let related = |key: &EntityKey| edges.get(key).unwrap_or(&none).iter();
+
+    let mut on_path = HashSet::from([root.clone()]);
+    let mut done = HashSet::new();
+    let mut stack = vec![(root.clone(), related(root))];
+    while let Some((key, next)) = stack.last_mut() {
+        match next.next() {
+            Some(to) if on_path.contains(to) => return true,
+            Some(to) if !done.contains(to) => {
+                on_path.insert(to.clone());
+                stack.push((to.clone(), related(to)));
+            }
+            Some(_) => {}
+            None => {
+                on_path.remove(key);
+                done.insert(key.clone());
+                stack.pop();
+            }
+        }
+    }
+    false
+}
+
+pub fn parse_entities(
+    contents: &[Value],
+    schema: Option<&Schema>,
+) -> Result<Entities, AuthorizationRequestError> {
+    match Entities::from_json_value(Value::Array(contents.to_vec()), schema) {
+        Ok(e) => Ok(e),
+        Err(err) => Err(AuthorizationRequestError::InvalidEntities(err.to_string())),
+    }
+}
+
+fn content_key(content: &Value) -> EntityKey {
+    let uid = &content["uid"];
+    (
+        uid["type"].as_str().unwrap_or_default().to_string(),
+        uid["id"].as_str().unwrap_or_default().to_string(),
+    )
+}
+
+fn uid_key(uid: &EntityUid) -> EntityKey {
+    (uid.type_name().to_string(), uid.id().as_ref().to_string())
+}
+
+fn entity_key(
+    uid: &str,
+    err: fn(String) -> AuthorizationRequestError,
+) -> Result<EntityKey, AuthorizationRequestError> {
+    match EntityUid::from_str(uid) {
+        Ok(uid) => Ok(uid_key(&uid)),
+        Err(e) => Err(err(e.to_string())),
+    }
+}
+
+/// Stored `roots` and their stored ancestors, following `entity_parents` without entering
+/// any entity in `visited`.
+async fn fetch_ancestor_contents(
+    pool: &SqlitePool,
+    roots: &[EntityKey],
+    visited: &[EntityKey],
+) -> Result<Vec<(Value, Option<u32>)>, AuthorizationRequestError> {
+    let query = "WITH RECURSIVE walk(etype, eid) AS (
+            SELECT json_extract(r.value, '$[0]'), json_extract(r.value, '$[1]') FROM json_each($1) r
+            UNION
+            SELECT ep.parent_etype, ep.parent_eid
+            FROM walk w
+            JOIN entities e ON e.etype = w.etype AND e.eid = w.eid
+            JOIN entity_parents ep ON ep.entity_id = e.id
+            WHERE NOT EXISTS (
+                SELECT 1 FROM json_each($2) v
+                WHERE json_extract(v.value, '$[0]') = ep.parent_etype
+                  AND json_extract(v.value, '$[1]') = ep.parent_eid
+            )
+        )
+        SELECT e.content, e.ttl FROM walk w JOIN entities e ON e.etype = w.etype AND e.eid = w.eid";
+
+    let rows = sqlx::query_as(query)
+        .bind(serde_json::json!(roots))
+        .bind(serde_json::json!(visited))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk_hierarchy, HierarchyDirection};
+    use crate::utils::test_utils::{insert_entity, memory_pool};
+
+    #[tokio::test]
+    async fn walk_hierarchy_should_limit_depth_and_detect_cycles() {
+        let pool = memory_pool().await;
+
+        // alice -> {Staff, Admins}, bob -> Staff, Staff -> Admins -> Staff, dave -> Ops
+        let entities = [
+            (
+                "User",
+                "alice",
+                r#"[{"type":"Group","id":"Staff"},{"type":"Group","id":"Admins"}]"#,
+            ),
+            ("User", "bob", r#"[{"type":"Group","id":"Staff"}]"#),
+            ("Group", "Staff", r#"[{"type":"Group","id":"Admins"}]"#),
+            ("Group", "Admins", r#"[{"type":"Group","id":"Staff"}]"#),
+            ("User", "dave", r#"[{"type":"Group","id":"Ops"}]"#),
+            ("Group", "Ops", "[]"),
+        ];
+        for (etype, eid, parents) in entities {
+            let content = format!(
+                r#"{{"uid":{{"type":"{}","id":"{}"}},"attrs":{{}},"parents":{}}}"#,
+                etype, eid, parents
+            );
+            insert_entity(&pool, serde_json::from_str(&content).unwrap()).await;
+        }
+
+        let alice = ("User".to_string(), "alice".to_string());
+        let ancestors = walk_hierarchy(&pool, &alice, HierarchyDirection::Ancestors, 5)
+            .await
+            .unwrap();
+        let found: Vec<_> = ancestors
+            .entities
+            .iter()
+            .map(|e| (e.uid.id.as_str(), e.depth))
+            .collect();
+        assert_eq!(found, vec![("Admins", 1), ("Staff", 1)]);
+        // Staff and Admins are members of each other, even though neither leads back to alice
+        assert!(ancestors.cycle && !ancestors.truncated);
+
+        let bob = ("User".to_string(), "bob".to_string());
+        let ancestors = walk_hierarchy(&pool, &bob, HierarchyDirection::Ancestors, 1)
+            .await
+            .unwrap();
+        let found: Vec<_> = ancestors
+            .entities
+            .iter()
+            .map(|e| e.uid.id.as_str())
+            .collect();
+        assert_eq!(found, vec!["Staff"]);
+        assert!(ancestors.truncated && !ancestors.cycle);
 
-        // Convert all entities fetched to serde_json::Value
+        let dave = ("User".to_string(), "dave".to_string());
+        let ancestors = walk_hierarchy(&pool, &dave, HierarchyDirection::Ancestors, 5)
+            .await
+            .unwrap();
+        assert_eq!(ancestors.entities.len(), 1);
+        assert!(!ancestors.cycle && !ancestors.truncated);
 
-        // Call Entities::from_json_value(json, None) to convert json value to entity
+        let admins = ("Group".to_string(), "Admins".to_string());
+        let members = walk_hierarchy(&pool, &admins, HierarchyDirection::Descendants, 1)
+            .await
+            .unwrap();
+        let found: Vec<_> = members.entities.iter().map(|e| e.uid.id.as_str()).collect();
+        assert_eq!(found, vec!["Staff", "alice"]);
+        assert!(members.truncated && members.cycle);
 
-        todo!();
+        let all = walk_hierarchy(&pool, &admins, HierarchyDirection::Descendants, 5)
+            .await
+            .unwrap();
+        let found: Vec<_> = all
+            .entities
+            .iter()
+            .map(|e| (e.uid.id.as_str(), e.depth))
+            .collect();
+        assert_eq!(found, vec![("Staff", 1), ("alice", 1), ("bob", 2)]);
+        assert!(all.cycle && !all.truncated);
     }
 }
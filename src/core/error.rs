@@ -1,17 +1,69 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
 use thiserror::Error;
 
+use super::structs::AuthorizationErrorResponse;
+
 #[derive(Error, Debug)]
 pub enum AuthorizationRequestError {
-    #[error("failed to parse principal")]
-    InvalidPrincipal,
-    #[error("failed to parse action")]
-    InvalidAction,
-    #[error("failed to parse resource")]
-    InvalidResource,
-    #[error("failed to parse context")]
-    InvalidContext,
-    #[error("failed to parse entities")]
-    InvalidEntities,
-    #[error("failed to parse policies")]
-    InvalidPolicies,
+    #[error("failed to parse principal: {0}")]
+    InvalidPrincipal(String),
+    #[error("failed to parse action: {0}")]
+    InvalidAction(String),
+    #[error("failed to parse resource: {0}")]
+    InvalidResource(String),
+    #[error("failed to parse context: {0}")]
+    InvalidContext(String),
+    #[error("failed to parse entities: {0}")]
+    InvalidEntities(String),
+    #[error("failed to parse request entities: {0}")]
+    InvalidRequestEntities(String),
+    #[error("request entities conflict with stored entities: {0}")]
+    ConflictingEntities(String),
+    #[error("failed to parse policy {0}: {1}")]
+    InvalidPolicy(String, String),
+    #[error("failed to parse active schema: {0}")]
+    InvalidSchema(String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl AuthorizationRequestError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthorizationRequestError::InvalidPrincipal(_) => "InvalidPrincipal",
+            AuthorizationRequestError::InvalidAction(_) => "InvalidAction",
+            AuthorizationRequestError::InvalidResource(_) => "InvalidResource",
+            AuthorizationRequestError::InvalidContext(_) => "InvalidContext",
+            AuthorizationRequestError::InvalidEntities(_) => "InvalidEntities",
+            AuthorizationRequestError::InvalidRequestEntities(_) => "InvalidRequestEntities",
+            AuthorizationRequestError::ConflictingEntities(_) => "ConflictingEntities",
+            AuthorizationRequestError::InvalidPolicy(_, _) => "InvalidPolicy",
+            AuthorizationRequestError::InvalidSchema(_) => "InvalidSchema",
+            AuthorizationRequestError::Database(_) => "Database",
+        }
+    }
+}
+
+impl ResponseError for AuthorizationRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            // 400, the caller sent something we could not evaluate
+            AuthorizationRequestError::InvalidPrincipal(_)
+            | AuthorizationRequestError::InvalidAction(_)
+            | AuthorizationRequestError::InvalidResource(_)
+            | AuthorizationRequestError::InvalidContext(_)
+            | AuthorizationRequestError::InvalidRequestEntities(_)
+            | AuthorizationRequestError::ConflictingEntities(_) => StatusCode::BAD_REQUEST,
+            // 500, stored policies or entities are broken
+            AuthorizationRequestError::InvalidEntities(_)
+            | AuthorizationRequestError::InvalidPolicy(_, _)
+            | AuthorizationRequestError::InvalidSchema(_)
+            | AuthorizationRequestError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(AuthorizationErrorResponse::from(self))
+    }
 }
@@ -1,37 +1,99 @@
-use cedar_policy::{Decision, Diagnostics, Entity};
+use std::collections::BTreeMap;
+
+use cedar_policy::{Decision, Diagnostics};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use super::error::AuthorizationRequestError;
+
 #[derive(Debug, Deserialize)]
 pub struct AuthorizationRequest {
     pub principal: String,
     pub action: String,
     pub resource: String,
     pub context: Option<Value>,
+    /// Cedar entities JSON array. Entities listed here take precedence over stored
+    /// entities with the same UID.
     pub entities: Option<Value>,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizationQuery {
+    #[serde(default)]
+    pub explain: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthorizationResponse {
     pub decision: Decision,
     pub diagnostics: Option<Diagnostics>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+/// Why a decision was reached: the policies that determined it and the policies that could
+/// not be evaluated.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Explanation {
+    pub determining: Vec<PolicyExplanation>,
+    pub errored: Vec<PolicyEvaluationError>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PolicyExplanation {
+    pub id: String,
+    pub content: String,
+    pub annotations: BTreeMap<String, String>,
+    /// `None` for policies that are not stored in the `policies` table, e.g. template links
+    pub search_tags: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PolicyEvaluationError {
+    pub id: Option<String>,
+    pub error: String,
 }
 
 impl AuthorizationResponse {
     pub fn deny() -> Self {
-        return Self {
+        Self {
             decision: Decision::Deny,
             diagnostics: None,
-        };
+            explanation: None,
+        }
     }
 
     pub fn authz_decision(decision: Decision, diagnostics: Diagnostics) -> Self {
-        return Self {
+        Self {
             decision,
             diagnostics: Some(diagnostics),
-        };
+            explanation: None,
+        }
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthorizationErrorResponse {
+    pub error: String,
+    pub message: String,
+}
+
+impl From<&AuthorizationRequestError> for AuthorizationErrorResponse {
+    fn from(err: &AuthorizationRequestError) -> Self {
+        Self {
+            error: err.kind().to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BatchAuthorizationResponse {
+    Decision(AuthorizationResponse),
+    Error(AuthorizationErrorResponse),
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Policy {
     pub id: String,
@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
 pub struct EntityInput {
     pub uid: UID,
-    pub attrs: HashMap<String, serde_json::Value>,
+    pub attrs: BTreeMap<String, serde_json::Value>,
     pub parents: Vec<Parent>,
+    /// Seconds the entity may be served from the entity cache, not part of the Cedar entity
+    #[serde(default, skip_serializing)]
+    pub ttl: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
@@ -21,6 +24,54 @@ pub struct Parent {
     pub id: String,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct HierarchyQuery {
+    /// Number of levels to walk, defaults to the maximum
+    pub depth: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Hierarchy {
+    pub entities: Vec<HierarchyEntity>,
+    /// Set when the walk stopped at the depth limit with entities left to visit
+    pub truncated: bool,
+    /// Set when the walked entities contain a cycle, i.e. an entity that is its own ancestor
+    pub cycle: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HierarchyEntity {
+    pub uid: UID,
+    /// Number of parent edges between this entity and the starting entity
+    pub depth: u32,
+}
+
+/// Result of deleting an entity, with the references to it that were found. References are
+/// only looked up when `ENTITY_DELETE_MODE` is not `allow`.
+#[derive(Serialize, Debug, Clone)]
+pub struct EntityRemoval {
+    pub id: String,
+    /// Entities that listed the deleted entity as a parent
+    pub children: Vec<UID>,
+    /// Policies that name the deleted entity
+    pub policies: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub rejected: Vec<RejectedEntity>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RejectedEntity {
+    /// Position of the entity in the imported array
+    pub index: usize,
+    pub error: String,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct Entity {
     pub id: String,
@@ -28,6 +79,8 @@ pub struct Entity {
     pub etype: String,
     pub content: serde_json::Value,
     pub search_tags: String,
+    pub ttl: Option<u32>,
+    pub version: i64,
     pub created_ts: String,
     pub updated_ts: String,
 }
@@ -1,2 +1,4 @@
 pub mod entities;
 pub mod policies;
+pub mod schemas;
+pub mod templates;
@@ -1,10 +1,34 @@
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+/// The ttl of a policy created without one
+pub const DEFAULT_POLICY_TTL: u32 = 1;
+
 #[derive(Deserialize, Serialize, Debug, Clone, sqlx::FromRow, Validate)]
 pub struct PolicyInput {
     #[validate(length(min = 1, message = "field can't be empty"))]
     pub content: String,
+    /// Defaults to `DEFAULT_POLICY_TTL` on create; an update without it keeps the policy's ttl
+    pub ttl: Option<u32>,
+    /// Recorded on the revision this write creates
+    pub author: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RollbackInput {
+    pub author: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct PolicyRevision {
+    pub policy_id: String,
+    pub revision: i64,
+    pub content: String,
+    pub author: Option<String>,
+    pub reason: Option<String>,
+    pub created_ts: String,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
@@ -13,6 +37,12 @@ pub struct Policy {
     pub ttl: i32,
     pub content: String,
     pub search_tags: serde_json::Value,
+    pub annotation_id: Option<String>,
+    pub description: Option<String>,
+    pub namespace: String,
+    /// Shared by every policy created from the same document
+    pub source_id: Option<String>,
+    pub version: i64,
     pub created_ts: String,
     pub updated_ts: String,
 }
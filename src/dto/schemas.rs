@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct SchemaInput {
+    pub content: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Schema {
+    pub id: String,
+    pub content: serde_json::Value,
+    pub active: bool,
+    pub created_ts: i64,
+}
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Serialize, Debug, Clone, sqlx::FromRow, Validate)]
+pub struct TemplateInput {
+    #[validate(length(min = 1, message = "field can't be empty"))]
+    pub content: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Template {
+    pub id: String,
+    pub content: String,
+    pub created_ts: String,
+    pub updated_ts: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LinkInput {
+    pub principal: Option<String>,
+    pub resource: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct TemplateLink {
+    pub id: String,
+    pub template_id: String,
+    pub principal: Option<String>,
+    pub resource: Option<String>,
+    pub created_ts: String,
+}
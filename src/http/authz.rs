@@ -1,25 +1,161 @@
-use crate::cedar::api::{fetch_entities, fetch_policies, prepare_cedar_request};
-use crate::core::structs::{AuthorizationRequest, AuthorizationResponse};
-use actix_web::{web, Responder};
-use cedar_policy::{Authorizer, Response};
+use crate::cedar::api::{explain_decision, fetch_entities, prepare_cedar_request};
+use crate::core::db::{fetch_entity_contents, parse_entities};
+use crate::core::error::AuthorizationRequestError;
+use crate::core::structs::{
+    AuthorizationErrorResponse, AuthorizationQuery, AuthorizationRequest, AuthorizationResponse,
+    BatchAuthorizationResponse,
+};
+use crate::routes::app_state::AppState;
+use actix_web::web;
+use cedar_policy::{Authorizer, Entities, Request};
+use std::collections::HashSet;
 
-pub async fn authorize(authz: web::Json<AuthorizationRequest>) -> impl Responder {
+pub async fn authorize(
+    app_state: web::Data<AppState>,
+    authz: web::Json<AuthorizationRequest>,
+    query: web::Query<AuthorizationQuery>,
+) -> Result<web::Json<AuthorizationResponse>, AuthorizationRequestError> {
+    let schema = app_state.schema.get();
     let authz_call = tokio::try_join!(
-        prepare_cedar_request(&authz),
-        fetch_policies(&authz),
-        fetch_entities(&authz)
+        prepare_cedar_request(&authz, schema.as_deref()),
+        fetch_entities(
+            &app_state.pool,
+            &app_state.entities,
+            &authz,
+            schema.as_deref().map(|s| &s.schema)
+        )
     );
 
-    let authz_response: Option<Response> = match authz_call {
-        Ok((req, pol, ent)) => Some(Authorizer::new().is_authorized(&req, &pol, &ent)),
-        Err(_err) => None,
+    let (req, ent) = match authz_call {
+        Ok(r) => r,
+        Err(err) => {
+            log::warn!("authorization request failed: {}", err);
+            return Err(err);
+        }
     };
 
-    return match authz_response {
-        Some(r) => web::Json(AuthorizationResponse::authz_decision(
-            r.decision(),
-            r.diagnostics().clone(),
-        )),
-        None => web::Json(AuthorizationResponse::deny()),
-    };
+    let pol = app_state.policies.get()?;
+    let r = Authorizer::new().is_authorized(&req, &pol, &ent);
+    let mut response = AuthorizationResponse::authz_decision(r.decision(), r.diagnostics().clone());
+    if query.explain {
+        response.explanation =
+            Some(explain_decision(&app_state.pool, &pol, r.diagnostics()).await?);
+    }
+    Ok(web::Json(response))
+}
+
+pub async fn authorize_batch(
+    app_state: web::Data<AppState>,
+    batch: web::Json<Vec<AuthorizationRequest>>,
+) -> Result<web::Json<Vec<BatchAuthorizationResponse>>, AuthorizationRequestError> {
+    let policies = app_state.policies.get()?;
+    let active = app_state.schema.get();
+    let schema = active.as_deref();
+
+    // Parse every item first so the stored entities they need can be fetched in one walk.
+    let mut roots = Vec::new();
+    let mut prepared: Vec<Result<(Request, Entities), AuthorizationRequestError>> = Vec::new();
+    for authz in batch.iter() {
+        let item = match prepare_cedar_request(authz, schema).await {
+            Ok(req) => authz
+                .inline_entities(schema.map(|s| &s.schema))
+                .and_then(|inline| {
+                    roots.extend(authz.entity_roots(&inline)?);
+                    Ok((req, inline))
+                }),
+            Err(err) => Err(err),
+        };
+        prepared.push(item);
+    }
+
+    let stored =
+        fetch_entity_contents(&app_state.pool, &app_state.entities, roots, HashSet::new()).await?;
+    let entities = parse_entities(&stored, schema.map(|s| &s.schema))?;
+
+    let authorizer = Authorizer::new();
+    let responses = prepared
+        .into_iter()
+        .zip(batch.iter())
+        .map(|(item, authz)| {
+            let (req, inline) = item?;
+            let r = if inline.iter().next().is_none() {
+                authorizer.is_authorized(&req, &policies, &entities)
+            } else {
+                let merged = authz.merge_entities(&stored, &inline, schema.map(|s| &s.schema))?;
+                authorizer.is_authorized(&req, &policies, &merged)
+            };
+            Ok(AuthorizationResponse::authz_decision(
+                r.decision(),
+                r.diagnostics().clone(),
+            ))
+        })
+        .map(|item: Result<_, AuthorizationRequestError>| match item {
+            Ok(r) => BatchAuthorizationResponse::Decision(r),
+            Err(err) => BatchAuthorizationResponse::Error(AuthorizationErrorResponse::from(&err)),
+        })
+        .collect();
+
+    Ok(web::Json(responses))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::authorize_batch;
+    use crate::utils::env_helper::IntegrityConfig;
+    use crate::utils::test_utils::{app_state, insert_entity};
+    use actix_web::{test, web, App};
+    use serde_json::{json, Value};
+
+    /// Inline entities only apply to their own item, so bob is an adult in exactly one.
+    #[actix_web::test]
+    async fn authorize_batch_should_answer_each_item_in_order() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        sqlx::query("INSERT INTO policies (id, ttl, content) VALUES ('adults', 1, $1)")
+            .bind(r#"permit(principal, action, resource) when { principal.age >= 18 };"#)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+        app_state.policies.reload(&app_state.pool).await.unwrap();
+        for (id, age) in [("alice", 30), ("bob", 12)] {
+            insert_entity(
+                &app_state.pool,
+                json!({"uid": {"type": "User", "id": id}, "attrs": {"age": age}, "parents": []}),
+            )
+            .await;
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app_state.clone()))
+                .route("/authorize/batch", web::post().to(authorize_batch)),
+        )
+        .await;
+
+        let item = |principal: &str| {
+            json!({"principal": principal, "action": r#"Action::"view""#,
+                   "resource": r#"Photo::"trip""#})
+        };
+        let mut adult_bob = item(r#"User::"bob""#);
+        adult_bob["entities"] =
+            json!([{"uid": {"type": "User", "id": "bob"}, "attrs": {"age": 40}, "parents": []}]);
+        let batch = json!([
+            item(r#"User::"alice""#),
+            item("not a uid"),
+            item(r#"User::"bob""#),
+            adult_bob,
+            item(r#"User::"bob""#),
+        ]);
+
+        let req = test::TestRequest::post()
+            .uri("/authorize/batch")
+            .set_json(batch);
+        let responses: Vec<Value> = test::call_and_read_body_json(&app, req.to_request()).await;
+        let answers: Vec<&str> = responses
+            .iter()
+            .map(|r| r["decision"].as_str().or(r["error"].as_str()).unwrap())
+            .collect();
+        assert_eq!(
+            answers,
+            vec!["Allow", "InvalidPrincipal", "Deny", "Allow", "Deny"]
+        );
+    }
 }
@@ -1,8 +1,5 @@
 use cedar_authorizer::routes::api_error::ApiError;
-pub mod dto;
-pub mod routes;
 mod server;
-pub mod utils;
 
 #[actix_web::main]
 async fn main() -> Result<(), ApiError> {
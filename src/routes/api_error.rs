@@ -1,3 +1,4 @@
+use crate::core::error::AuthorizationRequestError;
 use actix_web::http::StatusCode;
 use actix_web::ResponseError;
 use validator::ValidationErrors;
@@ -16,6 +17,14 @@ pub enum ApiError {
     #[error("Not Found: {0}")]
     NotFound(String),
 
+    // 409
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    // 412
+    #[error("Precondition Failed: {0}")]
+    PreconditionFailed(String),
+
     // 422
     #[error("{0}")]
     Validation(String),
@@ -28,6 +37,9 @@ pub enum ApiError {
 
     #[error(transparent)]
     Other(#[from] anyhow::Error),
+
+    #[error(transparent)]
+    Authorization(#[from] AuthorizationRequestError),
 }
 
 impl ResponseError for ApiError {
@@ -36,10 +48,13 @@ impl ResponseError for ApiError {
             ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             ApiError::_Forbidden(_) => StatusCode::FORBIDDEN,
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
             ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
             ApiError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::StdError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Authorization(err) => err.status_code(),
         }
     }
 }
@@ -62,6 +77,46 @@ impl From<ValidationErrors> for ApiError {
 
 impl From<sqlx::Error> for ApiError {
     fn from(value: sqlx::Error) -> Self {
+        if let sqlx::Error::Database(err) = &value {
+            // SQLITE_BUSY and SQLITE_LOCKED, including their extended codes: another
+            // connection is writing the same rows
+            let code = err.code().and_then(|code| code.parse::<i32>().ok());
+            if let Some(5 | 6) = code.map(|code| code & 0xff) {
+                return ApiError::Conflict(format!("concurrent write, retry the request: {}", err));
+            }
+            // SQLITE_CONSTRAINT_UNIQUE and SQLITE_CONSTRAINT_PRIMARYKEY: a concurrent request
+            // stored the same UID or @id after it was checked
+            if let Some(2067 | 1555) = code {
+                return ApiError::Conflict(err.to_string());
+            }
+        }
         ApiError::NotFound(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ApiError;
+    use crate::utils::test_utils::memory_pool;
+
+    #[tokio::test]
+    async fn unique_violation_should_be_a_conflict() {
+        let pool = memory_pool().await;
+
+        let insert =
+            "INSERT INTO entities (id, eid, etype, content) VALUES ($1, 'alice', 'User', '{}')";
+        sqlx::query(insert).bind("a").execute(&pool).await.unwrap();
+        let err = sqlx::query(insert)
+            .bind("b")
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(ApiError::from(err), ApiError::Conflict(_)));
+
+        let missing = sqlx::query_scalar::<_, String>("SELECT id FROM entities WHERE id = 'c'")
+            .fetch_one(&pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(ApiError::from(missing), ApiError::NotFound(_)));
+    }
+}
@@ -5,4 +5,15 @@ pub struct ApiResponse {
     pub status_code: String,
     pub message: String,
     pub data: serde_json::Value,
+    /// Set by list endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Pagination {
+    /// Number of rows matching the filters, across all pages
+    pub total: i64,
+    /// Cursor of the next page, `None` on the last page
+    pub next_cursor: Option<String>,
 }
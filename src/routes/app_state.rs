@@ -1,4 +1,13 @@
+use std::sync::Arc;
+
+use crate::cedar::cache::{EntityCache, PolicyCache, SchemaCache};
+use crate::utils::env_helper::IntegrityConfig;
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: sqlx::Pool<sqlx::Sqlite>,
+    pub policies: Arc<PolicyCache>,
+    pub schema: Arc<SchemaCache>,
+    pub entities: Arc<EntityCache>,
+    pub integrity: IntegrityConfig,
 }
@@ -1,76 +1,303 @@
-use crate::dto::entities::{Entity, EntityInput};
+use crate::cedar::api::policies_referencing;
+use crate::core::db::{walk_hierarchy, EntityKey, HierarchyDirection};
+use crate::dto::entities::{
+    Entity, EntityInput, EntityRemoval, HierarchyQuery, ImportSummary, RejectedEntity, UID,
+};
 use crate::routes::api_error::ApiError;
-use crate::routes::api_response::ApiResponse;
+use crate::routes::api_response::{ApiResponse, Pagination};
 use crate::routes::app_state::AppState;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::routes::pagination::{ListQuery, Listed, Listing};
+use crate::routes::preconditions::{etag, unmatched, Precondition};
+use crate::utils::env_helper::EntityDeleteMode;
+use crate::utils::json_patch::{apply_patch, merge_patch};
+use actix_web::web::Bytes;
+use actix_web::{delete, get, patch, post, put, web, HttpMessage, HttpRequest, HttpResponse};
+use cedar_policy::{Entities, EntityId, EntityTypeName, EntityUid, Schema};
 use chrono::Utc;
-use sqlx::{self, SqlitePool};
+use futures_util::{stream, TryStreamExt};
+use serde_json::Value;
+use sqlx::{self, QueryBuilder, Sqlite, SqlitePool, Transaction};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Deepest hierarchy walk the hierarchy endpoints allow
+const MAX_HIERARCHY_DEPTH: u32 = 32;
+
+/// Request body limit for `/import`, which carries the whole entity store
+const IMPORT_LIMIT: usize = 64 * 1024 * 1024;
+
+fn entity_key(entity_input: &EntityInput) -> EntityKey {
+    (entity_input.uid.r#type.clone(), entity_input.uid.id.clone())
+}
+
+/// The stored content of an entity. Object keys are sorted at every level, so the same
+/// entity always serializes to the same text and `upsert_entity` can compare it in SQL.
+fn entity_content(entity_input: &EntityInput) -> Result<Value, ApiError> {
+    Ok(sort_keys(serde_json::to_value(entity_input)?))
+}
+
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut members: Vec<(String, Value)> = map.into_iter().collect();
+            members.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(
+                members
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(sort_keys).collect()),
+        other => other,
+    }
+}
+
+fn validate_entity(app_state: &AppState, entity_input: &EntityInput) -> Result<(), ApiError> {
+    let schema = app_state.schema.get();
+    check_entity(entity_input, schema.as_deref().map(|s| &s.schema)).map_err(ApiError::Validation)
+}
+
+fn check_entity(entity_input: &EntityInput, schema: Option<&Schema>) -> Result<(), String> {
+    if let Err(err) = EntityTypeName::from_str(&entity_input.uid.r#type) {
+        return Err(format!(
+            "invalid entity type `{}`: {}",
+            entity_input.uid.r#type, err
+        ));
+    }
+
+    let entities = serde_json::json!([entity_input]);
+    match Entities::from_json_value(entities, schema) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Inserts the entity as row `id`, or replaces the content of the row that already has its
+/// UID. Returns `None` when the existing row already held the same content.
+async fn upsert_entity(
+    tr: &mut Transaction<'_, Sqlite>,
+    id: &str,
+    entity_input: &EntityInput,
+    current_time: &str,
+) -> Result<Option<Entity>, ApiError> {
+    let upsert_query = "INSERT INTO entities
+        (id,eid, etype, content, search_tags, ttl, created_ts, updated_ts)
+         VALUES($1,$2,$3,$4,$5,$6,$7,$8)
+         ON CONFLICT (etype, eid) DO UPDATE SET content = excluded.content, ttl = excluded.ttl,
+            updated_ts = excluded.created_ts, version = version + 1
+         WHERE content IS NOT excluded.content OR ttl IS NOT excluded.ttl
+         RETURNING id,eid,etype,content,search_tags,ttl,version,created_ts,updated_ts";
+
+    let written: Option<Entity> = sqlx::query_as(upsert_query)
+        .bind(id)
+        .bind(&entity_input.uid.id)
+        .bind(&entity_input.uid.r#type)
+        .bind(entity_content(entity_input)?)
+        .bind("".to_string())
+        .bind(entity_input.ttl)
+        .bind(current_time)
+        .bind("".to_string())
+        .fetch_optional(&mut *tr)
+        .await?;
+
+    if let Some(entity) = &written {
+        replace_parents(tr, &entity.id, entity_input).await?;
+    }
+    Ok(written)
+}
+
+/// Parents of the entity that are neither stored nor among the `pending` entities being
+/// written alongside it.
+async fn missing_parents(
+    pool: &SqlitePool,
+    entity_input: &EntityInput,
+    pending: &HashSet<EntityKey>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut missing = Vec::new();
+    for parent in &entity_input.parents {
+        let key = (parent.r#type.clone(), parent.id.clone());
+        if !pending.contains(&key) && find_entity_id(pool, &key.0, &key.1).await?.is_none() {
+            missing.push(format!("{}::\"{}\"", key.0, key.1));
+        }
+    }
+    Ok(missing)
+}
+
+/// Rejects parents that are not stored, when parent checks are enabled.
+async fn check_parents(app_state: &AppState, entity_input: &EntityInput) -> Result<(), ApiError> {
+    if !app_state.integrity.check_parents {
+        return Ok(());
+    }
+
+    let missing = missing_parents(&app_state.pool, entity_input, &HashSet::new()).await?;
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(format!(
+            "unknown parent entities: {}",
+            missing.join(", ")
+        )))
+    }
+}
+
+/// Rewrites the `entity_parents` edges of row `entity_id` to match the entity's parents.
+async fn replace_parents(
+    tr: &mut Transaction<'_, Sqlite>,
+    entity_id: &str,
+    entity_input: &EntityInput,
+) -> Result<(), ApiError> {
+    sqlx::query("DELETE FROM entity_parents WHERE entity_id = $1")
+        .bind(entity_id)
+        .execute(&mut *tr)
+        .await?;
+
+    for parent in &entity_input.parents {
+        sqlx::query(
+            "INSERT OR IGNORE INTO entity_parents (entity_id, parent_etype, parent_eid)
+             VALUES ($1, $2, $3)",
+        )
+        .bind(entity_id)
+        .bind(&parent.r#type)
+        .bind(&parent.id)
+        .execute(&mut *tr)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Rejects a UID that is already used by another row.
+async fn check_unique_uid(
+    pool: &SqlitePool,
+    entity_input: &EntityInput,
+    id: Option<&str>,
+) -> Result<(), ApiError> {
+    let existing: Option<String> = sqlx::query_scalar(
+        "SELECT id FROM entities WHERE etype = $1 AND eid = $2 AND id IS NOT $3",
+    )
+    .bind(&entity_input.uid.r#type)
+    .bind(&entity_input.uid.id)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    match existing {
+        Some(existing) => Err(ApiError::Conflict(format!(
+            "entity {}::\"{}\" already exists with id {}",
+            entity_input.uid.r#type, entity_input.uid.id, existing
+        ))),
+        None => Ok(()),
+    }
+}
 
 #[post("")]
 pub async fn add(
     app_state: web::Data<AppState>,
     entity_input: web::Json<EntityInput>,
-) -> Result<ApiResponse> {
+) -> Result<HttpResponse, ApiError> {
+    validate_entity(&app_state, &entity_input)?;
+    check_unique_uid(&app_state.pool, &entity_input, None).await?;
+    check_parents(&app_state, &entity_input).await?;
+
     let mut tr = app_state.pool.begin().await?;
 
     let current_time = Utc::now();
 
     let insert_query = "INSERT INTO entities 
-        (id,eid, etype, content, search_tags, created_ts, updated_ts)
-         VALUES($1,$2,$3,$4,$5,$6,$7)  RETURNING id ";
+        (id,eid, etype, content, search_tags, ttl, created_ts, updated_ts)
+         VALUES($1,$2,$3,$4,$5,$6,$7,$8)
+         RETURNING id,eid,etype,content,search_tags,ttl,version,created_ts,updated_ts";
 
-    let result: Result<String, sqlx::Error> = sqlx::query_scalar(insert_query)
+    let entity: Entity = sqlx::query_as(insert_query)
         .bind(uuid::Uuid::new_v4().to_string())
         .bind(entity_input.uid.id.clone())
         .bind(entity_input.uid.r#type.clone())
-        .bind(serde_json::to_value(entity_input)?)
+        .bind(entity_content(&entity_input)?)
         .bind("".to_string())
+        .bind(entity_input.ttl)
         .bind(current_time.to_rfc3339())
         .bind("".to_string())
         .fetch_one(&mut tr)
-        .await;
-
-    let id = match result {
-        Ok(id) => id,
-        Err(err) => {
-            tr.rollback().await?; // Rollback the transaction in case of an error
-            return Err(Error::from(err).into()); // Convert sqlx::Error to your custom error type
-        }
-    };
+        .await?;
+    replace_parents(&mut tr, &entity.id, &entity_input).await?;
 
     tr.commit().await?;
+    app_state.entities.evict(&entity_key(&entity_input));
 
-    Ok(ApiResponse {
-        status_code: StatusCode::OK,
-        message: "Successfully added entity",
-        data: Some(id),
-    })
+    entity_response(entity)
 }
 
-
 #[get("")]
-pub async fn get_all(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
-    match get_all_entities(&app_state.pool).await {
-        Ok(entities) => {
+pub async fn get_all(
+    app_state: web::Data<AppState>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let listing = query.listing()?;
+    match get_all_entities(&app_state.pool, query.etype.as_deref(), &listing).await {
+        Ok((entities, pagination)) => {
             let entities_value: serde_json::Value = serde_json::to_value(entities)?;
 
             Ok(HttpResponse::Ok().json(ApiResponse {
                 status_code: "200".to_string(),
                 message: "Successful".to_string(),
                 data: entities_value,
+                pagination: Some(pagination),
             }))
         }
         Err(e) => Err(ApiError::NotFound(e.to_string())),
     }
 }
 
-async fn get_all_entities(pool: &SqlitePool) -> Result<Vec<Entity>, sqlx::Error> {
-    let entities = sqlx::query_as::<sqlx::Sqlite, Entity>(
-        "SELECT id,eid,etype,content,search_tags,created_ts,updated_ts FROM entities",
-    )
-    .fetch_all(pool)
-    .await?;
-    Ok(entities)
+impl Listed for Entity {
+    fn id(&self) -> &str {
+        &self.id
+    }
+    fn created_ts(&self) -> &str {
+        &self.created_ts
+    }
+    fn updated_ts(&self) -> &str {
+        &self.updated_ts
+    }
+}
+
+async fn get_all_entities(
+    pool: &SqlitePool,
+    etype: Option<&str>,
+    listing: &Listing,
+) -> Result<(Vec<Entity>, Pagination), sqlx::Error> {
+    let push_filters = |query: &mut QueryBuilder<'_, Sqlite>| {
+        if let Some(etype) = etype {
+            query.push(" AND etype = ").push_bind(etype.to_string());
+        }
+        listing.push_filters(query);
+    };
+
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM entities WHERE 1 = 1");
+    push_filters(&mut count);
+    let (total,): (i64,) = count.build_query_as().fetch_one(pool).await?;
+
+    let mut select = QueryBuilder::new(
+        "SELECT id,eid,etype,content,search_tags,ttl,version,created_ts,updated_ts FROM entities
+         WHERE 1 = 1",
+    );
+    push_filters(&mut select);
+    listing.push_page(&mut select);
+    let mut entities: Vec<Entity> = select.build_query_as().fetch_all(pool).await?;
+
+    let pagination = listing.paginate(&mut entities, total);
+    Ok((entities, pagination))
+}
+
+#[get("/cache")]
+pub async fn cache_stats(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let stats_value: serde_json::Value = serde_json::to_value(app_state.entities.stats())?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: stats_value,
+        pagination: None,
+    }))
 }
 
 #[get("/{id}")]
@@ -81,22 +308,30 @@ pub async fn get_by_id(
     let entity_id = path.into_inner(); // Extract the ID from the path
 
     match get_entity_by_id(&app_state.pool, entity_id).await {
-        Ok(entities) => {
-            let entities_value: serde_json::Value = serde_json::to_value(entities)?;
-
-            Ok(HttpResponse::Ok().json(ApiResponse {
-                status_code: "200".to_string(),
-                message: "Successful".to_string(),
-                data: entities_value,
-            }))
-        }
+        Ok(entity) => entity_response(entity),
         Err(e) => Err(ApiError::NotFound(e.to_string())),
     }
 }
 
+/// Responds with a single entity row, tagged with its version.
+fn entity_response(entity: Entity) -> Result<HttpResponse, ApiError> {
+    let version = entity.version;
+    let entity_value: serde_json::Value = serde_json::to_value(entity)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(version))
+        .json(ApiResponse {
+            status_code: "200".to_string(),
+            message: "Successful".to_string(),
+            data: entity_value,
+            pagination: None,
+        }))
+}
+
 async fn get_entity_by_id(pool: &SqlitePool, id: String) -> Result<Entity, sqlx::Error> {
     let query =
-        "SELECT id,eid,etype,content,search_tags,created_ts,updated_ts FROM entities WHERE id = $1";
+        "SELECT id,eid,etype,content,search_tags,ttl,version,created_ts,updated_ts FROM entities
+         WHERE id = $1";
     let rows = sqlx::query_as::<_, Entity>(query)
         .bind(&id)
         .fetch_one(pool)
@@ -105,72 +340,1026 @@ async fn get_entity_by_id(pool: &SqlitePool, id: String) -> Result<Entity, sqlx:
     Ok(rows)
 }
 
+async fn find_entity_id(
+    pool: &SqlitePool,
+    etype: &str,
+    eid: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM entities WHERE etype = $1 AND eid = $2")
+        .bind(etype)
+        .bind(eid)
+        .fetch_optional(pool)
+        .await
+}
+
 #[put("/{id}")]
 pub async fn update(
     path: web::Path<String>,
     app_state: web::Data<AppState>,
     entity_input: web::Json<EntityInput>,
+    precondition: Precondition,
 ) -> Result<HttpResponse, ApiError> {
-    let id = path.into_inner();
+    update_entity(&app_state, path.into_inner(), &entity_input, precondition).await
+}
+
+async fn update_entity(
+    app_state: &AppState,
+    id: String,
+    entity_input: &EntityInput,
+    precondition: Precondition,
+) -> Result<HttpResponse, ApiError> {
+    validate_entity(app_state, entity_input)?;
+    check_unique_uid(&app_state.pool, entity_input, Some(&id)).await?;
+    check_parents(app_state, entity_input).await?;
+
+    // The update may change the UID, so the previous one has to be evicted as well. With
+    // `If-Match` the update below only applies to the row at the version read here.
+    let (etype, eid, version): (String, String, i64) =
+        sqlx::query_as("SELECT etype, eid, version FROM entities WHERE id = $1")
+            .bind(&id)
+            .fetch_one(&app_state.pool)
+            .await?;
+    let version = precondition.guard(version)?;
 
     let mut tr = app_state.pool.begin().await?;
 
     let current_time = Utc::now();
 
-    let query = "UPDATE entities SET etype = $1, content = $2, search_tags = $3,
-                        updated_ts =$4 , eid=$5 WHERE id = $6 RETURNING id";
+    let query = "UPDATE entities SET etype = $1, content = $2, search_tags = $3, ttl = $4,
+                        updated_ts =$5 , eid=$6, version = version + 1
+                        WHERE id = $7 AND ($8 IS NULL OR version = $8) RETURNING id, version";
 
-    let row: (String,) = sqlx::query_as(query)
+    let row: Option<(String, i64)> = sqlx::query_as(query)
         .bind(entity_input.uid.r#type.clone())
-        .bind(serde_json::to_value(entity_input.clone())?)
+        .bind(entity_content(entity_input)?)
         .bind("".to_string())
+        .bind(entity_input.ttl)
         .bind(current_time.to_rfc3339())
         .bind(entity_input.uid.id.clone())
-        .bind(id)
-        .fetch_one(&mut tr)
+        .bind(&id)
+        .bind(version)
+        .fetch_optional(&mut tr)
         .await?;
 
-    let updated_id = row.0;
+    let (updated_id, new_version) = match row {
+        Some(row) => row,
+        None => return Err(unmatched(&mut tr, "entities", &id).await),
+    };
+    replace_parents(&mut tr, &updated_id, entity_input).await?;
 
     tr.commit().await?;
-    Ok(HttpResponse::Ok().json(ApiResponse {
-        status_code: "200".to_string(),
-        message: "Successful".to_string(),
-        data: serde_json::Value::String(updated_id),
+    app_state.entities.evict(&(etype, eid));
+    app_state.entities.evict(&entity_key(entity_input));
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(new_version))
+        .json(ApiResponse {
+            status_code: "200".to_string(),
+            message: "Successful".to_string(),
+            data: serde_json::Value::String(updated_id),
+            pagination: None,
+        }))
+}
+
+/// Applies a JSON Patch (`application/json-patch+json`) or, for any other content type, a
+/// JSON Merge Patch to the `attrs` and `parents` of an entity.
+#[patch("/{id}")]
+pub async fn partial_update(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    patch: web::Json<Value>,
+    precondition: Precondition,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+
+    let (content, ttl, version): (Value, Option<u32>, i64) =
+        sqlx::query_as("SELECT content, ttl, version FROM entities WHERE id = $1")
+            .bind(&id)
+            .fetch_one(&app_state.pool)
+            .await?;
+    let version = precondition.guard(version)?;
+
+    let mut target = serde_json::json!({
+        "attrs": content["attrs"],
+        "parents": content["parents"],
+    });
+    if req.content_type() == "application/json-patch+json" {
+        apply_patch(&mut target, &patch).map_err(ApiError::Validation)?;
+    } else {
+        merge_patch(&mut target, &patch);
+    }
+    let other_keys = match target.as_object() {
+        Some(map) => map.keys().any(|key| key != "attrs" && key != "parents"),
+        None => true,
+    };
+    if other_keys {
+        return Err(ApiError::Validation(
+            "a patch may only change attrs and parents".to_string(),
+        ));
+    }
+
+    let mut entity_input: EntityInput = serde_json::from_value(serde_json::json!({
+        "uid": content["uid"],
+        "attrs": target["attrs"],
+        "parents": target["parents"],
     }))
+    .map_err(|err| ApiError::Validation(err.to_string()))?;
+    entity_input.ttl = ttl;
+    validate_entity(&app_state, &entity_input)?;
+    check_parents(&app_state, &entity_input).await?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let current_time = Utc::now();
+
+    let query = "UPDATE entities SET content = $1, updated_ts = $2, version = version + 1
+                 WHERE id = $3 AND ($4 IS NULL OR version = $4)
+                 RETURNING id,eid,etype,content,search_tags,ttl,version,created_ts,updated_ts";
+
+    let entity: Option<Entity> = sqlx::query_as(query)
+        .bind(entity_content(&entity_input)?)
+        .bind(current_time.to_rfc3339())
+        .bind(&id)
+        .bind(version)
+        .fetch_optional(&mut tr)
+        .await?;
+    let entity = match entity {
+        Some(entity) => entity,
+        None => return Err(unmatched(&mut tr, "entities", &id).await),
+    };
+    replace_parents(&mut tr, &id, &entity_input).await?;
+
+    tr.commit().await?;
+    app_state.entities.evict(&entity_key(&entity_input));
+
+    entity_response(entity)
 }
 
 #[delete("/{id}")]
 pub async fn remove(
     path: web::Path<String>,
     app_state: web::Data<AppState>,
+    precondition: Precondition,
 ) -> Result<HttpResponse, ApiError> {
-    let entity_id = path.into_inner();
+    remove_entity(&app_state, path.into_inner(), precondition).await
+}
+
+async fn remove_entity(
+    app_state: &AppState,
+    entity_id: String,
+    precondition: Precondition,
+) -> Result<HttpResponse, ApiError> {
+    let version: i64 = sqlx::query_scalar("SELECT version FROM entities WHERE id = $1")
+        .bind(&entity_id)
+        .fetch_one(&app_state.pool)
+        .await?;
+    let version = precondition.guard(version)?;
+
     let mut tr = app_state.pool.begin().await?;
 
+    // Deleting first takes the write lock, so the references read below cannot change
+    // before the transaction commits or, when they block the delete, rolls back.
     let query = "
     DELETE FROM entities
-    WHERE id = $1 RETURNING id;
+    WHERE id = $1 AND ($2 IS NULL OR version = $2) RETURNING id, etype, eid;
 ";
 
-    let row: (String,) = sqlx::query_as(query)
-        .bind(entity_id)
-        .fetch_one(&mut tr)
+    let deleted: Option<(String, String, String)> = sqlx::query_as(query)
+        .bind(&entity_id)
+        .bind(version)
+        .fetch_optional(&mut tr)
         .await?;
-    let deleted_id = row.0;
+    let (deleted_id, etype, eid) = match deleted {
+        Some(deleted) => deleted,
+        None => return Err(unmatched(&mut tr, "entities", &entity_id).await),
+    };
+
+    let mode = app_state.integrity.on_entity_delete;
+    let (dependents, policies) = match mode {
+        EntityDeleteMode::Allow => (Vec::new(), Vec::new()),
+        _ => {
+            let dependents: Vec<(String, String, String)> = sqlx::query_as(
+                "SELECT e.id, e.etype, e.eid
+                 FROM entity_parents ep JOIN entities e ON e.id = ep.entity_id
+                 WHERE ep.parent_etype = $1 AND ep.parent_eid = $2
+                 ORDER BY e.etype, e.eid",
+            )
+            .bind(&etype)
+            .bind(&eid)
+            .fetch_all(&mut tr)
+            .await?;
+            let uid = EntityTypeName::from_str(&etype)
+                .ok()
+                .zip(EntityId::from_str(&eid).ok())
+                .map(|(type_name, id)| EntityUid::from_type_name_and_id(type_name, id));
+            let policies = match uid {
+                Some(uid) => policies_referencing(&*app_state.policies.get()?, &uid),
+                None => Vec::new(),
+            };
+            (dependents, policies)
+        }
+    };
+
+    if mode == EntityDeleteMode::Block && !(dependents.is_empty() && policies.is_empty()) {
+        let dependents: Vec<String> = dependents
+            .iter()
+            .map(|(_, etype, eid)| format!("{}::\"{}\"", etype, eid))
+            .collect();
+        return Err(ApiError::Conflict(format!(
+            "entity {}::\"{}\" is referenced by entities [{}] and policies [{}]",
+            etype,
+            eid,
+            dependents.join(", "),
+            policies.join(", ")
+        )));
+    }
+    if mode == EntityDeleteMode::Cascade {
+        detach_children(&mut tr, &dependents, &(etype.clone(), eid.clone())).await?;
+    }
+
     tr.commit().await?;
+    app_state.entities.evict(&(etype, eid));
+    for (_, etype, eid) in &dependents {
+        app_state.entities.evict(&(etype.clone(), eid.clone()));
+    }
+
+    let removal_value = serde_json::to_value(EntityRemoval {
+        id: deleted_id,
+        children: dependents
+            .into_iter()
+            .map(|(_, r#type, id)| UID { r#type, id })
+            .collect(),
+        policies,
+    })?;
 
     Ok(HttpResponse::Ok().json(ApiResponse {
         status_code: "200".to_string(),
         message: "Successful".to_string(),
-        data: serde_json::Value::String(deleted_id),
+        data: removal_value,
+        pagination: None,
     }))
 }
 
+/// Removes `parent` from the parents of every row in `dependents`.
+async fn detach_children(
+    tr: &mut Transaction<'_, Sqlite>,
+    dependents: &[(String, String, String)],
+    (parent_etype, parent_eid): &EntityKey,
+) -> Result<(), ApiError> {
+    let current_time = Utc::now();
+
+    for (child_id, _, _) in dependents {
+        let mut content: Value = sqlx::query_scalar("SELECT content FROM entities WHERE id = $1")
+            .bind(child_id)
+            .fetch_one(&mut *tr)
+            .await?;
+        if let Some(Value::Array(parents)) = content.get_mut("parents") {
+            parents.retain(|p| p["type"] != *parent_etype || p["id"] != *parent_eid);
+        }
+
+        sqlx::query(
+            "UPDATE entities SET content = $1, updated_ts = $2, version = version + 1
+             WHERE id = $3",
+        )
+        .bind(&content)
+        .bind(current_time.to_rfc3339())
+        .bind(child_id)
+        .execute(&mut *tr)
+        .await?;
+        sqlx::query(
+            "DELETE FROM entity_parents
+             WHERE entity_id = $1 AND parent_etype = $2 AND parent_eid = $3",
+        )
+        .bind(child_id)
+        .bind(parent_etype)
+        .bind(parent_eid)
+        .execute(&mut *tr)
+        .await?;
+    }
+    Ok(())
+}
+
+#[get("/{etype}/{eid}")]
+pub async fn get_by_uid(
+    path: web::Path<(String, String)>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let (etype, eid) = path.into_inner();
+
+    match find_entity_id(&app_state.pool, &etype, &eid).await? {
+        Some(id) => entity_response(get_entity_by_id(&app_state.pool, id).await?),
+        None => Err(ApiError::NotFound(format!(
+            "entity {}::\"{}\" not found",
+            etype, eid
+        ))),
+    }
+}
+
+/// Creates or replaces the entity with the UID in the path. Without `If-Match` this is
+/// idempotent: writing the same content again leaves the row and its version untouched.
+#[put("/{etype}/{eid}")]
+pub async fn upsert(
+    path: web::Path<(String, String)>,
+    app_state: web::Data<AppState>,
+    entity_input: web::Json<EntityInput>,
+    precondition: Precondition,
+) -> Result<HttpResponse, ApiError> {
+    let (etype, eid) = path.into_inner();
+    if entity_input.uid.r#type != etype || entity_input.uid.id != eid {
+        return Err(ApiError::Validation(format!(
+            "entity uid {}::\"{}\" does not match the path {}::\"{}\"",
+            entity_input.uid.r#type, entity_input.uid.id, etype, eid
+        )));
+    }
+
+    if precondition.is_present() {
+        return match find_entity_id(&app_state.pool, &etype, &eid).await? {
+            Some(id) => update_entity(&app_state, id, &entity_input, precondition).await,
+            None => Err(ApiError::PreconditionFailed(format!(
+                "entity {}::\"{}\" does not exist",
+                etype, eid
+            ))),
+        };
+    }
+
+    validate_entity(&app_state, &entity_input)?;
+    check_parents(&app_state, &entity_input).await?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let current_time = Utc::now();
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let written = upsert_entity(&mut tr, &id, &entity_input, &current_time.to_rfc3339()).await?;
+
+    let entity = match written {
+        Some(entity) => entity,
+        // Nothing changed, so the conflicting row was left as it is
+        None => {
+            sqlx::query_as(
+                "SELECT id,eid,etype,content,search_tags,ttl,version,created_ts,updated_ts
+                 FROM entities WHERE etype = $1 AND eid = $2",
+            )
+            .bind(&etype)
+            .bind(&eid)
+            .fetch_one(&mut tr)
+            .await?
+        }
+    };
+
+    tr.commit().await?;
+    app_state.entities.evict(&(etype, eid));
+
+    entity_response(entity)
+}
+
+#[delete("/{etype}/{eid}")]
+pub async fn remove_by_uid(
+    path: web::Path<(String, String)>,
+    app_state: web::Data<AppState>,
+    precondition: Precondition,
+) -> Result<HttpResponse, ApiError> {
+    let (etype, eid) = path.into_inner();
+
+    match find_entity_id(&app_state.pool, &etype, &eid).await? {
+        Some(id) => remove_entity(&app_state, id, precondition).await,
+        None => Err(ApiError::NotFound(format!(
+            "entity {}::\"{}\" not found",
+            etype, eid
+        ))),
+    }
+}
+
+/// Entities the entity in the path is a member of, directly or through its parents.
+#[get("/{etype}/{eid}/ancestors")]
+pub async fn ancestors(
+    path: web::Path<(String, String)>,
+    query: web::Query<HierarchyQuery>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    hierarchy_response(
+        &app_state.pool,
+        path.into_inner(),
+        HierarchyDirection::Ancestors,
+        query.depth,
+    )
+    .await
+}
+
+/// Entities that are members of the entity in the path, directly or through their parents.
+#[get("/{etype}/{eid}/descendants")]
+pub async fn descendants(
+    path: web::Path<(String, String)>,
+    query: web::Query<HierarchyQuery>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    hierarchy_response(
+        &app_state.pool,
+        path.into_inner(),
+        HierarchyDirection::Descendants,
+        query.depth,
+    )
+    .await
+}
+
+/// Entities that list the entity in the path as a direct parent.
+#[get("/{etype}/{eid}/children")]
+pub async fn children(
+    path: web::Path<(String, String)>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    hierarchy_response(
+        &app_state.pool,
+        path.into_inner(),
+        HierarchyDirection::Descendants,
+        Some(1),
+    )
+    .await
+}
+
+async fn hierarchy_response(
+    pool: &SqlitePool,
+    (etype, eid): EntityKey,
+    direction: HierarchyDirection,
+    depth: Option<u32>,
+) -> Result<HttpResponse, ApiError> {
+    let depth = depth.unwrap_or(MAX_HIERARCHY_DEPTH);
+    if depth == 0 || depth > MAX_HIERARCHY_DEPTH {
+        return Err(ApiError::Validation(format!(
+            "depth must be between 1 and {}",
+            MAX_HIERARCHY_DEPTH
+        )));
+    }
+    if find_entity_id(pool, &etype, &eid).await?.is_none() {
+        return Err(ApiError::NotFound(format!(
+            "entity {}::\"{}\" not found",
+            etype, eid
+        )));
+    }
+
+    let hierarchy = walk_hierarchy(pool, &(etype, eid), direction, depth).await?;
+    let hierarchy_value: serde_json::Value = serde_json::to_value(hierarchy)?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: hierarchy_value,
+        pagination: None,
+    }))
+}
+
+/// Upserts a Cedar entities JSON array in one transaction. Entries that are malformed, fail
+/// validation or repeat an earlier UID are skipped and reported by their index.
+pub async fn import(
+    app_state: web::Data<AppState>,
+    items: web::Json<Vec<Value>>,
+) -> Result<HttpResponse, ApiError> {
+    let active = app_state.schema.get();
+    let schema = active.as_deref().map(|s| &s.schema);
+    let mut summary = ImportSummary::default();
+
+    let mut seen = HashSet::new();
+    let mut accepted: Vec<(usize, EntityInput)> = Vec::new();
+    for (index, item) in items.into_inner().into_iter().enumerate() {
+        let checked = serde_json::from_value::<EntityInput>(item)
+            .map_err(|err| err.to_string())
+            .and_then(|entity_input| {
+                if !seen.insert(entity_key(&entity_input)) {
+                    return Err("duplicate of an earlier entity in the import".to_string());
+                }
+                if let Err(err) = EntityTypeName::from_str(&entity_input.uid.r#type) {
+                    return Err(format!("invalid entity type: {}", err));
+                }
+                Ok(entity_input)
+            });
+        match checked {
+            Ok(entity_input) => accepted.push((index, entity_input)),
+            Err(error) => summary.rejected.push(RejectedEntity { index, error }),
+        }
+    }
+
+    // Validate everything in one pass and only look for the culprits if that fails
+    let all: Vec<&EntityInput> = accepted.iter().map(|(_, e)| e).collect();
+    if Entities::from_json_value(serde_json::to_value(&all)?, schema).is_err() {
+        accepted.retain(
+            |(index, entity_input)| match check_entity(entity_input, schema) {
+                Ok(_) => true,
+                Err(error) => {
+                    summary.rejected.push(RejectedEntity {
+                        index: *index,
+                        error,
+                    });
+                    false
+                }
+            },
+        );
+    }
+
+    if app_state.integrity.check_parents {
+        let pending: HashSet<EntityKey> = accepted.iter().map(|(_, e)| entity_key(e)).collect();
+        let mut checked = Vec::with_capacity(accepted.len());
+        for (index, entity_input) in accepted {
+            let missing = missing_parents(&app_state.pool, &entity_input, &pending).await?;
+            if missing.is_empty() {
+                checked.push((index, entity_input));
+            } else {
+                summary.rejected.push(RejectedEntity {
+                    index,
+                    error: format!("unknown parent entities: {}", missing.join(", ")),
+                });
+            }
+        }
+        accepted = checked;
+    }
+    summary.rejected.sort_by_key(|r| r.index);
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let current_time = Utc::now().to_rfc3339();
+
+    for (_, entity_input) in &accepted {
+        let id = uuid::Uuid::new_v4().to_string();
+        match upsert_entity(&mut tr, &id, entity_input, &current_time).await? {
+            Some(entity) if entity.id == id => summary.inserted += 1,
+            Some(_) => summary.updated += 1,
+            None => summary.unchanged += 1,
+        }
+    }
+
+    tr.commit().await?;
+    for (_, entity_input) in &accepted {
+        app_state.entities.evict(&entity_key(entity_input));
+    }
+
+    let summary_value: serde_json::Value = serde_json::to_value(summary)?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: summary_value,
+        pagination: None,
+    }))
+}
+
+/// Streams every stored entity as one Cedar entities JSON array. Entities with a ttl carry
+/// it as a `ttl` member, which `/import` reads back; other Cedar tooling expects it removed.
+#[get("/export")]
+pub async fn export(app_state: web::Data<AppState>) -> HttpResponse {
+    let pool = app_state.pool.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, sqlx::Error>>(64);
+
+    tokio::spawn(async move {
+        let mut rows = sqlx::query_scalar::<_, Value>(
+            "SELECT CASE WHEN ttl IS NULL THEN content ELSE json_set(content, '$.ttl', ttl) END
+             FROM entities ORDER BY etype, eid",
+        )
+        .fetch(&pool);
+
+        let mut separator = "[";
+        loop {
+            let chunk = match rows.try_next().await {
+                Ok(Some(content)) => {
+                    let chunk = format!("{}{}", separator, content);
+                    separator = ",";
+                    Ok(Bytes::from(chunk))
+                }
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+        let end = if separator == "[" { "[]" } else { "]" };
+        let _ = tx.send(Ok(Bytes::from_static(end.as_bytes()))).await;
+    });
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(body)
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(add)
-        .service(update)
-        .service(remove)
-        .service(get_all)
-        .service(get_by_id);
+    cfg.service(
+        web::resource("/import")
+            .app_data(web::JsonConfig::default().limit(IMPORT_LIMIT))
+            .route(web::post().to(import)),
+    )
+    .service(add)
+    .service(update)
+    .service(partial_update)
+    .service(remove)
+    .service(get_all)
+    .service(cache_stats)
+    .service(export)
+    .service(get_by_id)
+    .service(get_by_uid)
+    .service(upsert)
+    .service(remove_by_uid)
+    .service(ancestors)
+    .service(descendants)
+    .service(children);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::config;
+    use crate::utils::env_helper::{EntityDeleteMode, IntegrityConfig};
+    use crate::utils::test_utils::{app_state, call};
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, web, App};
+    use serde_json::{json, Value};
+    use sqlx::SqlitePool;
+
+    macro_rules! service {
+        ($app_state:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($app_state.clone()))
+                    .service(web::scope("/entities").configure(config)),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn upsert_should_keep_the_version_of_unchanged_content() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        let app = service!(app_state);
+
+        let written = json!({
+            "uid": {"type": "User", "id": "alice"},
+            "attrs": {
+                "age": 30,
+                "name": "alice",
+                "address": {"city": "Paris", "zip": "75001"},
+                "roles": ["admin", "dev"],
+            },
+            "parents": [],
+        });
+        let reordered = json!({
+            "parents": [],
+            "attrs": {
+                "roles": ["admin", "dev"],
+                "address": {"zip": "75001", "city": "Paris"},
+                "name": "alice",
+                "age": 30,
+            },
+            "uid": {"id": "alice", "type": "User"},
+        });
+        let mut changed = written.clone();
+        changed["attrs"]["age"] = json!(31);
+
+        let mut versions = Vec::new();
+        for body in [&written, &reordered, &written, &changed] {
+            let entity = call!(
+                app,
+                test::TestRequest::put()
+                    .uri("/entities/User/alice")
+                    .set_json(body)
+            );
+            versions.push(entity["version"].as_i64().unwrap());
+        }
+        assert_eq!(versions, vec![1, 1, 1, 2]);
+    }
+
+    #[actix_web::test]
+    async fn import_should_count_inserted_updated_and_unchanged_entities() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        let app = service!(app_state);
+
+        let user = |id: &str, age: i64| json!({"uid": {"type": "User", "id": id}, "attrs": {"age": age}, "parents": []});
+        let first = call!(
+            app,
+            test::TestRequest::post()
+                .uri("/entities/import")
+                .set_json(json!([user("alice", 30), user("bob", 40)]))
+        );
+        assert_eq!(first["inserted"], 2);
+
+        let second = call!(
+            app,
+            test::TestRequest::post()
+                .uri("/entities/import")
+                .set_json(json!([
+                    user("alice", 30),
+                    user("bob", 41),
+                    user("carol", 50)
+                ]))
+        );
+        assert_eq!(
+            second,
+            json!({"inserted": 1, "updated": 1, "unchanged": 1, "rejected": []})
+        );
+    }
+
+    #[actix_web::test]
+    async fn export_should_round_trip_through_import() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        let app = service!(app_state);
+
+        let alice = json!({"uid": {"type": "User", "id": "alice"}, "attrs": {}, "parents": [],
+                           "ttl": 60});
+        let entity = call!(
+            app,
+            test::TestRequest::put()
+                .uri("/entities/User/alice")
+                .set_json(&alice)
+        );
+        assert_eq!(entity["ttl"], 60);
+
+        let req = test::TestRequest::get()
+            .uri("/entities/export")
+            .to_request();
+        let exported: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(exported, json!([alice]));
+
+        let summary = call!(
+            app,
+            test::TestRequest::post()
+                .uri("/entities/import")
+                .set_json(exported)
+        );
+        assert_eq!(summary["unchanged"], 1);
+    }
+
+    #[actix_web::test]
+    async fn import_should_report_rejected_entities_by_index() {
+        let integrity = IntegrityConfig {
+            check_parents: true,
+            ..Default::default()
+        };
+        let (app_state, _dir) = app_state(integrity).await;
+        let schema = json!({"": {
+            "entityTypes": {
+                "User": {
+                    "memberOfTypes": ["Group"],
+                    "shape": {"type": "Record", "attributes": {"age": {"type": "Long"}}},
+                },
+                "Group": {},
+            },
+            "actions": {},
+        }});
+        sqlx::query("INSERT INTO schemas (id, content, active) VALUES ('s', $1, 1)")
+            .bind(schema)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+        app_state.schema.reload(&app_state.pool).await.unwrap();
+        let app = service!(app_state);
+
+        let items = json!([
+            {"uid": {"type": "User", "id": "alice"}, "attrs": {"age": 30},
+             "parents": [{"type": "Group", "id": "admins"}]},
+            "not an entity",
+            {"uid": {"type": "User", "id": "bob"}, "attrs": {"age": "old"}, "parents": []},
+            {"uid": {"type": "User", "id": "alice"}, "attrs": {"age": 31}, "parents": []},
+            {"uid": {"type": "User", "id": "carol"}, "attrs": {"age": 50},
+             "parents": [{"type": "Group", "id": "missing"}]},
+            {"uid": {"type": "Group", "id": "admins"}, "attrs": {}, "parents": []},
+            {"uid": {"type": "not a type", "id": "x"}, "attrs": {}, "parents": []},
+        ]);
+        let summary = call!(
+            app,
+            test::TestRequest::post()
+                .uri("/entities/import")
+                .set_json(items)
+        );
+
+        assert_eq!(summary["inserted"], 2);
+        let rejected: Vec<u64> = summary["rejected"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(rejected, vec![1, 2, 3, 4, 6]);
+        assert!(summary["rejected"][3]["error"]
+            .as_str()
+            .unwrap()
+            .contains("Group::\"missing\""));
+    }
+
+    #[actix_web::test]
+    async fn remove_should_follow_the_delete_mode() {
+        for mode in [
+            EntityDeleteMode::Allow,
+            EntityDeleteMode::Block,
+            EntityDeleteMode::Cascade,
+            EntityDeleteMode::Report,
+        ] {
+            let integrity = IntegrityConfig {
+                on_entity_delete: mode,
+                ..Default::default()
+            };
+            let (app_state, _dir) = app_state(integrity).await;
+            sqlx::query("INSERT INTO policies (id, ttl, content) VALUES ('admins-view', 1, $1)")
+                .bind(r#"permit(principal in Group::"admins", action, resource);"#)
+                .execute(&app_state.pool)
+                .await
+                .unwrap();
+            app_state.policies.reload(&app_state.pool).await.unwrap();
+            let app = service!(app_state);
+
+            for (uri, body) in [
+                (
+                    "/entities/Group/admins",
+                    json!({"uid": {"type": "Group", "id": "admins"}, "attrs": {}, "parents": []}),
+                ),
+                (
+                    "/entities/User/alice",
+                    json!({"uid": {"type": "User", "id": "alice"}, "attrs": {},
+                           "parents": [{"type": "Group", "id": "admins"}]}),
+                ),
+            ] {
+                call!(app, test::TestRequest::put().uri(uri).set_json(body));
+            }
+
+            let req = test::TestRequest::delete().uri("/entities/Group/admins");
+            let res = test::call_service(&app, req.to_request()).await;
+            if mode == EntityDeleteMode::Block {
+                assert_eq!(res.status(), StatusCode::CONFLICT);
+                let req = test::TestRequest::get().uri("/entities/Group/admins");
+                let res = test::call_service(&app, req.to_request()).await;
+                assert_eq!(res.status(), StatusCode::OK);
+                continue;
+            }
+            assert_eq!(res.status(), StatusCode::OK);
+            let removal: Value = test::read_body_json(res).await;
+            let (children, policies) = match mode {
+                EntityDeleteMode::Allow => (json!([]), json!([])),
+                _ => (
+                    json!([{"type": "User", "id": "alice"}]),
+                    json!(["admins-view"]),
+                ),
+            };
+            assert_eq!(removal["data"]["children"], children, "{:?}", mode);
+            assert_eq!(removal["data"]["policies"], policies, "{:?}", mode);
+
+            let alice = call!(app, test::TestRequest::get().uri("/entities/User/alice"));
+            let parents = match mode {
+                EntityDeleteMode::Cascade => json!([]),
+                _ => json!([{"type": "Group", "id": "admins"}]),
+            };
+            assert_eq!(alice["content"]["parents"], parents, "{:?}", mode);
+        }
+    }
+
+    #[actix_web::test]
+    async fn check_parents_should_reject_unknown_parents() {
+        let integrity = IntegrityConfig {
+            check_parents: true,
+            ..Default::default()
+        };
+        let (app_state, _dir) = app_state(integrity).await;
+        let app = service!(app_state);
+
+        let alice = json!({"uid": {"type": "User", "id": "alice"}, "attrs": {},
+                           "parents": [{"type": "Group", "id": "admins"}]});
+        let requests = || {
+            [
+                test::TestRequest::post().uri("/entities").set_json(&alice),
+                test::TestRequest::put()
+                    .uri("/entities/User/alice")
+                    .set_json(&alice),
+            ]
+        };
+        for req in requests() {
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        call!(
+            app,
+            test::TestRequest::put()
+                .uri("/entities/Group/admins")
+                .set_json(
+                    json!({"uid": {"type": "Group", "id": "admins"}, "attrs": {},
+                                 "parents": []})
+                )
+        );
+        let [add, _] = requests();
+        let res = test::call_service(&app, add.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    async fn parents_of(pool: &SqlitePool, id: &str) -> Vec<String> {
+        sqlx::query_scalar(
+            "SELECT parent_etype || '::' || parent_eid FROM entity_parents
+             WHERE entity_id = $1 ORDER BY parent_eid",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    macro_rules! patch_target {
+        ($app:expr) => {{
+            for group in ["admins", "staff"] {
+                call!(
+                    $app,
+                    test::TestRequest::put()
+                        .uri(&format!("/entities/Group/{}", group))
+                        .set_json(json!({"uid": {"type": "Group", "id": group}, "attrs": {},
+                                         "parents": []}))
+                );
+            }
+            let alice = call!(
+                $app,
+                test::TestRequest::put()
+                    .uri("/entities/User/alice")
+                    .set_json(json!({"uid": {"type": "User", "id": "alice"},
+                                     "attrs": {"age": 30},
+                                     "parents": [{"type": "Group", "id": "admins"}],
+                                     "ttl": 60}))
+            );
+            alice["id"].as_str().unwrap().to_string()
+        }};
+    }
+
+    fn patch(id: &str, content_type: &str, body: Value, if_match: &str) -> test::TestRequest {
+        test::TestRequest::patch()
+            .uri(&format!("/entities/{}", id))
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .insert_header((header::IF_MATCH, if_match))
+            .set_payload(body.to_string())
+    }
+
+    #[actix_web::test]
+    async fn partial_update_should_apply_a_json_patch() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        let app = service!(app_state);
+        let id = patch_target!(app);
+
+        let operations = json!([
+            {"op": "replace", "path": "/attrs/age", "value": 31},
+            {"op": "replace", "path": "/parents", "value": [{"type": "Group", "id": "staff"}]},
+        ]);
+        // The same document read as a merge patch replaces the whole target with an array
+        let res = test::call_service(
+            &app,
+            patch(&id, "application/json", operations.clone(), "*").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let content_type = "application/json-patch+json";
+        let entity = call!(app, patch(&id, content_type, operations.clone(), r#""1""#));
+        assert_eq!(entity["version"], 2);
+        assert_eq!(entity["ttl"], 60);
+        assert_eq!(entity["content"]["attrs"], json!({"age": 31}));
+        assert_eq!(parents_of(&app_state.pool, &id).await, vec!["Group::staff"]);
+
+        let uid = json!([{"op": "add", "path": "/uid", "value": {"type": "User", "id": "bob"}}]);
+        let res = test::call_service(&app, patch(&id, content_type, uid, "*").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let res = test::call_service(
+            &app,
+            patch(&id, content_type, operations, r#""1""#).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[actix_web::test]
+    async fn partial_update_should_apply_a_merge_patch() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        let app = service!(app_state);
+        let id = patch_target!(app);
+
+        let content_type = "application/merge-patch+json";
+        let changes = json!({
+            "attrs": {"age": null, "name": "alice"},
+            "parents": [{"type": "Group", "id": "admins"}, {"type": "Group", "id": "staff"}],
+        });
+        let entity = call!(app, patch(&id, content_type, changes.clone(), r#""1""#));
+        assert_eq!(entity["version"], 2);
+        assert_eq!(entity["ttl"], 60);
+        assert_eq!(entity["content"]["attrs"], json!({"name": "alice"}));
+        assert_eq!(
+            entity["content"]["uid"],
+            json!({"type": "User", "id": "alice"})
+        );
+        assert_eq!(
+            parents_of(&app_state.pool, &id).await,
+            vec!["Group::admins", "Group::staff"]
+        );
+
+        let uid = json!({"uid": {"type": "User", "id": "bob"}});
+        let res = test::call_service(&app, patch(&id, content_type, uid, "*").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let res = test::call_service(
+            &app,
+            patch(&id, content_type, changes, r#""1""#).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    }
 }
@@ -2,8 +2,14 @@ pub mod api_error;
 pub mod entities_controller;
 pub mod health_check;
 pub mod policies_controller;
+pub mod schemas_controller;
+pub mod templates_controller;
 pub use entities_controller::config as entities_config;
 pub use health_check::health_check;
 pub use policies_controller::config as policies_config;
+pub use schemas_controller::config as schemas_config;
+pub use templates_controller::config as templates_config;
 pub mod api_response;
 pub mod app_state;
+pub mod pagination;
+pub mod preconditions;
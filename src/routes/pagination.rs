@@ -0,0 +1,245 @@
+use crate::routes::api_error::ApiError;
+use crate::routes::api_response::Pagination;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{QueryBuilder, Sqlite};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+/// When a row was last written: `updated_ts` is empty until the first update
+const LAST_WRITE: &str = "COALESCE(NULLIF(updated_ts, ''), created_ts)";
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedTs,
+    UpdatedTs,
+    Id,
+}
+
+impl SortField {
+    fn name(self) -> &'static str {
+        match self {
+            SortField::CreatedTs => "created_ts",
+            SortField::UpdatedTs => "updated_ts",
+            SortField::Id => "id",
+        }
+    }
+
+    fn expression(self) -> &'static str {
+        match self {
+            SortField::CreatedTs => "created_ts",
+            SortField::UpdatedTs => LAST_WRITE,
+            SortField::Id => "id",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters of the list endpoints.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ListQuery {
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// Only rows created or updated at or after this RFC 3339 timestamp
+    pub updated_since: Option<String>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Only entities of this type
+    pub etype: Option<String>,
+}
+
+/// A row of a list endpoint.
+pub trait Listed {
+    fn id(&self) -> &str;
+    fn created_ts(&self) -> &str;
+    fn updated_ts(&self) -> &str;
+}
+
+/// A checked `ListQuery`, applied to a `SELECT ... WHERE <filters>` query with `push_filters`
+/// and `push_page`.
+#[derive(Debug, Clone)]
+pub struct Listing {
+    limit: u32,
+    sort: SortField,
+    order: SortOrder,
+    after: Option<(String, String)>,
+    updated_since: Option<String>,
+}
+
+impl ListQuery {
+    pub fn listing(&self) -> Result<Listing, ApiError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(ApiError::Validation(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+
+        let updated_since = match &self.updated_since {
+            Some(since) => match DateTime::parse_from_rfc3339(since) {
+                Ok(since) => Some(since.with_timezone(&Utc).to_rfc3339()),
+                Err(err) => {
+                    return Err(ApiError::Validation(format!(
+                        "invalid updated_since `{}`: {}",
+                        since, err
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        let after = match &self.cursor {
+            Some(cursor) => Some(decode_cursor(cursor, self.sort)?),
+            None => None,
+        };
+
+        Ok(Listing {
+            limit,
+            sort: self.sort,
+            order: self.order,
+            after,
+            updated_since,
+        })
+    }
+}
+
+impl Listing {
+    pub fn push_filters(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        if let Some(since) = &self.updated_since {
+            query
+                .push(format!(" AND {} >= ", LAST_WRITE))
+                .push_bind(since.clone());
+        }
+    }
+
+    /// Adds the cursor, the order and the limit. One row more than the limit is fetched to
+    /// tell whether another page follows.
+    pub fn push_page(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        let key = self.sort.expression();
+        let (comparison, direction) = match self.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        if let Some((value, id)) = &self.after {
+            query
+                .push(format!(" AND ({} {} ", key, comparison))
+                .push_bind(value.clone())
+                .push(format!(" OR ({} = ", key))
+                .push_bind(value.clone())
+                .push(format!(" AND id {} ", comparison))
+                .push_bind(id.clone())
+                .push("))");
+        }
+        query
+            .push(format!(
+                " ORDER BY {} {}, id {} LIMIT ",
+                key, direction, direction
+            ))
+            .push_bind(self.limit + 1);
+    }
+
+    /// Drops the extra row fetched by `push_page` and points the next cursor at the last row.
+    pub fn paginate<T: Listed>(&self, rows: &mut Vec<T>, total: i64) -> Pagination {
+        let mut next_cursor = None;
+        if rows.len() > self.limit as usize {
+            rows.truncate(self.limit as usize);
+            next_cursor = rows.last().map(|row| self.cursor_of(row));
+        }
+        Pagination { total, next_cursor }
+    }
+
+    fn cursor_of<T: Listed>(&self, row: &T) -> String {
+        let value = match self.sort {
+            SortField::CreatedTs => row.created_ts(),
+            SortField::UpdatedTs if row.updated_ts().is_empty() => row.created_ts(),
+            SortField::UpdatedTs => row.updated_ts(),
+            SortField::Id => row.id(),
+        };
+        let cursor = serde_json::json!([self.sort.name(), value, row.id()]);
+        URL_SAFE_NO_PAD.encode(cursor.to_string())
+    }
+}
+
+fn decode_cursor(cursor: &str, sort: SortField) -> Result<(String, String), ApiError> {
+    let invalid = || ApiError::Validation(format!("invalid cursor `{}`", cursor));
+
+    let json = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let (name, value, id): (String, String, String) =
+        serde_json::from_slice(&json).map_err(|_| invalid())?;
+    if name != sort.name() {
+        return Err(ApiError::Validation(format!(
+            "cursor was issued for sort `{}`",
+            name
+        )));
+    }
+    Ok((value, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ListQuery, Listed, SortField};
+
+    struct Row(&'static str, &'static str, &'static str);
+
+    impl Listed for Row {
+        fn id(&self) -> &str {
+            self.0
+        }
+        fn created_ts(&self) -> &str {
+            self.1
+        }
+        fn updated_ts(&self) -> &str {
+            self.2
+        }
+    }
+
+    #[test]
+    fn paginate_should_issue_a_cursor_for_the_next_page() {
+        let query = ListQuery {
+            limit: Some(2),
+            sort: SortField::UpdatedTs,
+            ..Default::default()
+        };
+        let listing = query.listing().unwrap();
+
+        let mut rows = vec![Row("a", "1", ""), Row("b", "2", "5"), Row("c", "3", "")];
+        let pagination = listing.paginate(&mut rows, 7);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(pagination.total, 7);
+
+        let next = ListQuery {
+            cursor: pagination.next_cursor,
+            ..query.clone()
+        };
+        assert_eq!(
+            next.listing().unwrap().after,
+            Some(("5".to_string(), "b".to_string()))
+        );
+
+        let resorted = ListQuery {
+            sort: SortField::Id,
+            ..next
+        };
+        assert!(resorted.listing().is_err());
+
+        let mut last = vec![Row("c", "3", "")];
+        assert_eq!(listing.paginate(&mut last, 7).next_cursor, None);
+    }
+}
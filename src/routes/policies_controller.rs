@@ -1,67 +1,304 @@
-use crate::dto::policies::{Policy, PolicyInput};
+use crate::cedar::api::{
+    parse_policy, policy_annotations, split_policies, validate_policies, PolicyAnnotations,
+};
+use crate::dto::policies::{
+    Policy, PolicyInput, PolicyRevision, RollbackInput, DEFAULT_POLICY_TTL,
+};
 use crate::routes::api_error::ApiError;
-use crate::routes::api_response::ApiResponse;
+use crate::routes::api_response::{ApiResponse, Pagination};
 use crate::routes::app_state::AppState;
+use crate::routes::pagination::{ListQuery, Listed, Listing};
+use crate::routes::preconditions::{etag, unmatched, Precondition};
 use actix_web::{delete, get, post, put, web, HttpResponse};
+use cedar_policy::PolicySet;
 use chrono::Utc;
-use sqlx::SqlitePool;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool, Transaction};
+use std::collections::HashSet;
+use validator::Validate;
+
+async fn validate_policy(
+    app_state: &AppState,
+    id: &str,
+    content: &str,
+) -> Result<PolicyAnnotations, ApiError> {
+    let policy = parse_policy(id, content).map_err(ApiError::Validation)?;
+    let annotations = policy_annotations(&policy);
+
+    if let Some(annotation_id) = &annotations.id {
+        let taken: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM policies WHERE namespace = $1 AND annotation_id = $2 AND id <> $3",
+        )
+        .bind(&annotations.namespace)
+        .bind(annotation_id)
+        .bind(id)
+        .fetch_optional(&app_state.pool)
+        .await?;
+        if let Some(other) = taken {
+            return Err(ApiError::Conflict(format!(
+                "@id `{}` is already used by policy {}",
+                annotation_id, other
+            )));
+        }
+    }
+
+    if let Some(schema) = app_state.schema.get() {
+        let policies = PolicySet::from_policies([policy]).map_err(anyhow::Error::from)?;
+        validate_policies(&policies, &schema.schema)
+            .map_err(|errors| ApiError::Validation(errors.join("\n")))?;
+    }
+
+    Ok(annotations)
+}
+
+/// Prefixes an error about one statement of a document with the statement's index, since
+/// the id it was validated under is not known to the client yet.
+fn in_statement(index: usize, err: ApiError) -> ApiError {
+    match err {
+        ApiError::Validation(message) => {
+            ApiError::Validation(format!("statement {}: {}", index, message))
+        }
+        ApiError::Conflict(message) => {
+            ApiError::Conflict(format!("statement {}: {}", index, message))
+        }
+        other => other,
+    }
+}
+
+/// Appends the next revision of a policy's content to its history.
+async fn insert_revision(
+    tr: &mut Transaction<'_, Sqlite>,
+    policy_id: &str,
+    content: &str,
+    author: Option<String>,
+    reason: Option<String>,
+) -> Result<i64, ApiError> {
+    let query =
+        "INSERT INTO policy_revisions (policy_id, revision, content, author, reason, created_ts)
+        SELECT $1, COALESCE(MAX(revision), 0) + 1, $2, $3, $4, $5
+        FROM policy_revisions WHERE policy_id = $1 RETURNING revision";
+
+    let revision: i64 = sqlx::query_scalar(query)
+        .bind(policy_id)
+        .bind(content)
+        .bind(author)
+        .bind(reason)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_one(&mut *tr)
+        .await?;
+    Ok(revision)
+}
+
+/// Reads the current version of a policy and checks it against `If-Match`. The read runs
+/// outside the write's transaction, which then only changes the row at the returned version,
+/// or at any version without `If-Match`.
+async fn current_version(
+    pool: &SqlitePool,
+    policy_id: &str,
+    precondition: &Precondition,
+) -> Result<Option<i64>, ApiError> {
+    let version: i64 = sqlx::query_scalar("SELECT version FROM policies WHERE id = $1")
+        .bind(policy_id)
+        .fetch_one(pool)
+        .await?;
+    precondition.guard(version)
+}
+
+/// Overwrites the content of a policy, at `version` if given, and records it as a new
+/// revision, returning the policy's new version. The policy keeps its ttl.
+async fn replace_content(
+    tr: &mut Transaction<'_, Sqlite>,
+    policy_id: &str,
+    version: Option<i64>,
+    content: &str,
+    annotations: PolicyAnnotations,
+    author: Option<String>,
+    reason: Option<String>,
+) -> Result<i64, ApiError> {
+    let query = "UPDATE policies SET content = $1, search_tags = $2,
+                        annotation_id = $3, description = $4, namespace = $5,
+                        updated_ts = $6, version = version + 1
+                        WHERE id = $7 AND ($8 IS NULL OR version = $8) RETURNING version";
+    let new_version: Option<i64> = sqlx::query_scalar(query)
+        .bind(content)
+        .bind(serde_json::Value::Object(Default::default()))
+        .bind(annotations.id)
+        .bind(annotations.description)
+        .bind(annotations.namespace)
+        .bind(Utc::now().to_rfc3339())
+        .bind(policy_id)
+        .bind(version)
+        .fetch_optional(&mut *tr)
+        .await?;
+    let new_version = match new_version {
+        Some(new_version) => new_version,
+        None => return Err(unmatched(tr, "policies", policy_id).await),
+    };
+
+    insert_revision(tr, policy_id, content, author, reason).await?;
+    Ok(new_version)
+}
+
+/// Resolves the `{id}` path segment, which is either the row id or the policy's `@id`
+/// annotation, optionally qualified as `Namespace::id`.
+async fn resolve_policy_id(pool: &SqlitePool, key: String) -> Result<String, ApiError> {
+    let ids: Vec<String> = sqlx::query_scalar(
+        "SELECT id FROM policies WHERE id = $1 OR annotation_id = $1
+         OR (namespace <> '' AND namespace || '::' || annotation_id = $1)",
+    )
+    .bind(&key)
+    .fetch_all(pool)
+    .await?;
+
+    if ids.contains(&key) {
+        return Ok(key);
+    }
+    match ids.as_slice() {
+        [] => Err(ApiError::NotFound(format!("policy {} not found", key))),
+        [id] => Ok(id.clone()),
+        _ => Err(ApiError::Conflict(format!(
+            "@id `{}` is used in several namespaces, qualify it as Namespace::{}",
+            key, key
+        ))),
+    }
+}
 
 #[post("")]
 pub async fn add(
     app_state: web::Data<AppState>,
     policy_input: web::Json<PolicyInput>,
 ) -> Result<HttpResponse, ApiError> {
+    policy_input.validate()?;
+
+    // Every statement in the document becomes its own row, so diagnostics map back to it
+    let statements = split_policies(&policy_input.content).map_err(ApiError::Validation)?;
+    if statements.is_empty() {
+        return Err(ApiError::Validation(
+            "document contains no policies".to_string(),
+        ));
+    }
+
+    let mut policies = Vec::new();
+    let mut annotation_ids = HashSet::new();
+    for (index, content) in statements.into_iter().enumerate() {
+        let policy_id = uuid::Uuid::new_v4().to_string();
+        let annotations = validate_policy(&app_state, &policy_id, &content)
+            .await
+            .map_err(|err| in_statement(index, err))?;
+        if let Some(annotation_id) = &annotations.id {
+            if !annotation_ids.insert((annotations.namespace.clone(), annotation_id.clone())) {
+                return Err(ApiError::Conflict(format!(
+                    "statement {}: @id `{}` is used by more than one policy in the document",
+                    index, annotation_id
+                )));
+            }
+        }
+        policies.push((policy_id, content, annotations));
+    }
+
+    let source_id = uuid::Uuid::new_v4().to_string();
+
     let mut tr = app_state.pool.begin().await?;
 
     let current_time = Utc::now();
 
     let insert_query = "INSERT INTO policies 
-        (id, ttl, content, search_tags, created_ts, updated_ts)
-         VALUES($1,$2,$3,$4,$5,$6) RETURNING id";
+        (id, ttl, content, search_tags, annotation_id, description, namespace, source_id,
+         created_ts, updated_ts)
+         VALUES($1,$2,$3,$4,$5,$6,$7,$8,$9,$10) RETURNING id";
 
-    let row: (String,) = sqlx::query_as(insert_query)
-        .bind(uuid::Uuid::new_v4().to_string())
-        .bind(1)
-        .bind(&policy_input.content)
-        .bind(serde_json::Value::Object(Default::default()))
-        .bind(current_time.to_rfc3339())
-        .bind("".to_string())
-        .fetch_one(&mut tr)
+    let mut ids = Vec::new();
+    for (policy_id, content, annotations) in policies {
+        let row: (String,) = sqlx::query_as(insert_query)
+            .bind(policy_id)
+            .bind(policy_input.ttl.unwrap_or(DEFAULT_POLICY_TTL))
+            .bind(&content)
+            .bind(serde_json::Value::Object(Default::default()))
+            .bind(annotations.id)
+            .bind(annotations.description)
+            .bind(annotations.namespace)
+            .bind(&source_id)
+            .bind(current_time.to_rfc3339())
+            .bind("".to_string())
+            .fetch_one(&mut tr)
+            .await?;
+        insert_revision(
+            &mut tr,
+            &row.0,
+            &content,
+            policy_input.author.clone(),
+            policy_input.reason.clone(),
+        )
         .await?;
+        ids.push(serde_json::Value::String(row.0));
+    }
 
-    let id = row.0;
     tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
 
     Ok(HttpResponse::Ok().json(ApiResponse {
         status_code: "200".to_string(),
         message: "Successful".to_string(),
-        data: serde_json::Value::String(id),
+        data: serde_json::Value::Array(ids),
+        pagination: None,
     }))
 }
 
 #[get("")]
-pub async fn get_all(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
-    match get_all_policies(&app_state.pool).await {
-        Ok(policies) => {
+pub async fn get_all(
+    app_state: web::Data<AppState>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if query.etype.is_some() {
+        return Err(ApiError::Validation(
+            "etype only applies to entities".to_string(),
+        ));
+    }
+    let listing = query.listing()?;
+    match get_all_policies(&app_state.pool, &listing).await {
+        Ok((policies, pagination)) => {
             let policies_value: serde_json::Value = serde_json::to_value(policies)?;
 
             Ok(HttpResponse::Ok().json(ApiResponse {
                 status_code: "200".to_string(),
                 message: "Successful".to_string(),
                 data: policies_value,
+                pagination: Some(pagination),
             }))
         }
         Err(e) => Err(ApiError::NotFound(e.to_string())),
     }
 }
 
-async fn get_all_policies(pool: &SqlitePool) -> Result<Vec<Policy>, sqlx::Error> {
-    let policies = sqlx::query_as::<sqlx::Sqlite, Policy>(
-        "SELECT id,ttl,content,search_tags,created_ts,updated_ts FROM policies",
-    )
-    .fetch_all(pool)
-    .await?;
-    Ok(policies)
+impl Listed for Policy {
+    fn id(&self) -> &str {
+        &self.id
+    }
+    fn created_ts(&self) -> &str {
+        &self.created_ts
+    }
+    fn updated_ts(&self) -> &str {
+        &self.updated_ts
+    }
+}
+
+async fn get_all_policies(
+    pool: &SqlitePool,
+    listing: &Listing,
+) -> Result<(Vec<Policy>, Pagination), sqlx::Error> {
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM policies WHERE 1 = 1");
+    listing.push_filters(&mut count);
+    let (total,): (i64,) = count.build_query_as().fetch_one(pool).await?;
+
+    let mut select = QueryBuilder::new(
+        "SELECT id,ttl,content,search_tags,annotation_id,description,namespace,source_id,
+         version,created_ts,updated_ts FROM policies WHERE 1 = 1",
+    );
+    listing.push_filters(&mut select);
+    listing.push_page(&mut select);
+    let mut policies: Vec<Policy> = select.build_query_as().fetch_all(pool).await?;
+
+    let pagination = listing.paginate(&mut policies, total);
+    Ok((policies, pagination))
 }
 
 #[put("/{id}")]
@@ -69,56 +306,86 @@ pub async fn update(
     path: web::Path<String>,
     app_state: web::Data<AppState>,
     policy_input: web::Json<PolicyInput>,
+    precondition: Precondition,
 ) -> Result<HttpResponse, ApiError> {
-    let policy_id = path.into_inner();
+    let policy_id = resolve_policy_id(&app_state.pool, path.into_inner()).await?;
 
-    let mut tr = app_state.pool.begin().await?;
+    policy_input.validate()?;
 
-    let current_time = Utc::now();
+    let annotations = validate_policy(&app_state, &policy_id, &policy_input.content).await?;
 
-    let query = "UPDATE policies SET ttl = $1, content = $2, search_tags = $3,
-                        created_ts = $4 , updated_ts =$5 WHERE id = $6 RETURNING id";
-    let row: (String,) = sqlx::query_as(query)
-        .bind(2)
-        .bind(&policy_input.content)
-        .bind(serde_json::Value::Object(Default::default()))
-        .bind("".to_string())
-        .bind(current_time.to_rfc3339())
-        .bind(policy_id)
-        .fetch_one(&mut tr)
-        .await?;
-    let updated_id = row.0;
+    let version = current_version(&app_state.pool, &policy_id, &precondition).await?;
+
+    let mut tr = app_state.pool.begin().await?;
+    let new_version = replace_content(
+        &mut tr,
+        &policy_id,
+        version,
+        &policy_input.content,
+        annotations,
+        policy_input.author.clone(),
+        policy_input.reason.clone(),
+    )
+    .await?;
+    if let Some(ttl) = policy_input.ttl {
+        sqlx::query("UPDATE policies SET ttl = $1 WHERE id = $2")
+            .bind(ttl)
+            .bind(&policy_id)
+            .execute(&mut tr)
+            .await?;
+    }
     tr.commit().await?;
-    Ok(HttpResponse::Ok().json(ApiResponse {
-        status_code: "200".to_string(),
-        message: "Successful".to_string(),
-        data: serde_json::Value::String(updated_id),
-    }))
+    app_state.policies.refresh(&app_state.pool).await;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(new_version))
+        .json(ApiResponse {
+            status_code: "200".to_string(),
+            message: "Successful".to_string(),
+            data: serde_json::Value::String(policy_id),
+            pagination: None,
+        }))
 }
 
 #[delete("/{id}")]
 pub async fn remove(
     path: web::Path<String>,
     app_state: web::Data<AppState>,
+    precondition: Precondition,
 ) -> Result<HttpResponse, ApiError> {
-    let policy_id = path.into_inner();
+    let policy_id = resolve_policy_id(&app_state.pool, path.into_inner()).await?;
+    let version = current_version(&app_state.pool, &policy_id, &precondition).await?;
     let mut tr = app_state.pool.begin().await?;
     let query = "
     DELETE FROM policies
-    WHERE id = $1 RETURNING id;
+    WHERE id = $1 AND ($2 IS NULL OR version = $2) RETURNING id, content;
 ";
 
-    let row: (String,) = sqlx::query_as(query)
-        .bind(policy_id)
-        .fetch_one(&mut tr)
+    let deleted: Option<(String, String)> = sqlx::query_as(query)
+        .bind(&policy_id)
+        .bind(version)
+        .fetch_optional(&mut tr)
         .await?;
-    let deleted_id = row.0; //
+    let (deleted_id, content) = match deleted {
+        Some(deleted) => deleted,
+        None => return Err(unmatched(&mut tr, "policies", &policy_id).await),
+    };
+    // The history outlives the policy and ends with the content it had when deleted
+    insert_revision(
+        &mut tr,
+        &deleted_id,
+        &content,
+        None,
+        Some("deleted".to_string()),
+    )
+    .await?;
     tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
 
     Ok(HttpResponse::Ok().json(ApiResponse {
         status_code: "200".to_string(),
         message: "Successful".to_string(),
         data: serde_json::Value::String(deleted_id),
+        pagination: None,
     }))
 }
 
@@ -127,17 +394,21 @@ pub async fn get_by_id(
     path: web::Path<String>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
-    let policy_id = path.into_inner();
+    let policy_id = resolve_policy_id(&app_state.pool, path.into_inner()).await?;
 
     match get_policy_by_id(&app_state.pool, policy_id).await {
-        Ok(policies) => {
-            let policies_value: serde_json::Value = serde_json::to_value(policies)?;
+        Ok(policy) => {
+            let version = policy.version;
+            let policies_value: serde_json::Value = serde_json::to_value(policy)?;
 
-            Ok(HttpResponse::Ok().json(ApiResponse {
-                status_code: "200".to_string(),
-                message: "Successful".to_string(),
-                data: policies_value,
-            }))
+            Ok(HttpResponse::Ok()
+                .insert_header(etag(version))
+                .json(ApiResponse {
+                    status_code: "200".to_string(),
+                    message: "Successful".to_string(),
+                    data: policies_value,
+                    pagination: None,
+                }))
         }
         Err(e) => Err(ApiError::NotFound(e.to_string())),
     }
@@ -145,7 +416,8 @@ pub async fn get_by_id(
 
 async fn get_policy_by_id(pool: &SqlitePool, id: String) -> Result<Policy, sqlx::Error> {
     let policy = sqlx::query_as::<sqlx::Sqlite, Policy>(
-        "SELECT id,ttl,content,search_tags,created_ts,updated_ts FROM policies WHERE id = ?",
+        "SELECT id,ttl,content,search_tags,annotation_id,description,namespace,source_id,
+         version,created_ts,updated_ts FROM policies WHERE id = ?",
     )
     .bind(id)
     .fetch_one(pool)
@@ -154,10 +426,200 @@ async fn get_policy_by_id(pool: &SqlitePool, id: String) -> Result<Policy, sqlx:
     Ok(policy)
 }
 
+#[get("/{id}/history")]
+pub async fn get_history(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let key = path.into_inner();
+    let policy_id = match resolve_policy_id(&app_state.pool, key.clone()).await {
+        Ok(policy_id) => policy_id,
+        // A deleted policy's history is still kept under its row id
+        Err(ApiError::NotFound(_)) => key,
+        Err(err) => return Err(err),
+    };
+
+    let revisions = sqlx::query_as::<sqlx::Sqlite, PolicyRevision>(
+        "SELECT policy_id,revision,content,author,reason,created_ts FROM policy_revisions
+         WHERE policy_id = ? ORDER BY revision",
+    )
+    .bind(&policy_id)
+    .fetch_all(&app_state.pool)
+    .await?;
+    if revisions.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "policy {} not found",
+            policy_id
+        )));
+    }
+    let revisions_value: serde_json::Value = serde_json::to_value(revisions)?;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: revisions_value,
+        pagination: None,
+    }))
+}
+
+#[post("/{id}/rollback/{rev}")]
+pub async fn rollback(
+    path: web::Path<(String, i64)>,
+    app_state: web::Data<AppState>,
+    rollback_input: Option<web::Json<RollbackInput>>,
+    precondition: Precondition,
+) -> Result<HttpResponse, ApiError> {
+    let (key, revision) = path.into_inner();
+    let policy_id = resolve_policy_id(&app_state.pool, key).await?;
+    let rollback_input = rollback_input.map(|i| i.into_inner()).unwrap_or_default();
+
+    let content: String = sqlx::query_scalar(
+        "SELECT content FROM policy_revisions WHERE policy_id = $1 AND revision = $2",
+    )
+    .bind(&policy_id)
+    .bind(revision)
+    .fetch_one(&app_state.pool)
+    .await?;
+
+    // The schema or other policies may have changed since this revision was written
+    let annotations = validate_policy(&app_state, &policy_id, &content).await?;
+
+    let reason = rollback_input
+        .reason
+        .unwrap_or_else(|| format!("rollback to revision {}", revision));
+
+    let version = current_version(&app_state.pool, &policy_id, &precondition).await?;
+    let mut tr = app_state.pool.begin().await?;
+    let new_version = replace_content(
+        &mut tr,
+        &policy_id,
+        version,
+        &content,
+        annotations,
+        rollback_input.author,
+        Some(reason),
+    )
+    .await?;
+    tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(new_version))
+        .json(ApiResponse {
+            status_code: "200".to_string(),
+            message: "Successful".to_string(),
+            data: serde_json::Value::String(policy_id),
+            pagination: None,
+        }))
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(add)
         .service(update)
         .service(remove)
         .service(get_all)
-        .service(get_by_id);
+        .service(get_by_id)
+        .service(get_history)
+        .service(rollback);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::config;
+    use crate::utils::env_helper::IntegrityConfig;
+    use crate::utils::test_utils::{app_state, call};
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, web, App};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn revisions_should_survive_updates_rollbacks_and_deletes() {
+        let (app_state, _dir) = app_state(IntegrityConfig::default()).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app_state.clone()))
+                .service(web::scope("/policies").configure(config)),
+        )
+        .await;
+        let view = r#"permit(principal, action == Action::"view", resource);"#;
+        let edit = r#"permit(principal, action == Action::"edit", resource);"#;
+
+        let ids = call!(
+            app,
+            test::TestRequest::post()
+                .uri("/policies")
+                .set_json(json!({"content": view, "author": "ann"}))
+        );
+        let uri = format!("/policies/{}", ids[0].as_str().unwrap());
+
+        let req = test::TestRequest::put()
+            .uri(&uri)
+            .insert_header((header::IF_MATCH, r#""1""#))
+            .set_json(json!({"content": edit, "reason": "edit only"}));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), r#""2""#);
+
+        let req = test::TestRequest::post().uri(&format!("{}/rollback/1", uri));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), r#""3""#);
+        let policy = call!(app, test::TestRequest::get().uri(&uri));
+        assert_eq!(policy["content"], view);
+
+        // Revision 1 no longer validates once the schema drops the view action
+        let schema = json!({"": {
+            "entityTypes": {"User": {}},
+            "actions": {"edit": {"appliesTo": {"principalTypes": ["User"],
+                                               "resourceTypes": ["User"]}}},
+        }});
+        sqlx::query("INSERT INTO schemas (id, content, active) VALUES ('s', $1, 1)")
+            .bind(schema)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+        app_state.schema.reload(&app_state.pool).await.unwrap();
+        for (revision, status) in [(1, StatusCode::UNPROCESSABLE_ENTITY), (2, StatusCode::OK)] {
+            let req = test::TestRequest::post().uri(&format!("{}/rollback/{}", uri, revision));
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), status, "revision {}", revision);
+        }
+
+        let req = test::TestRequest::delete().uri(&uri);
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = test::TestRequest::get().uri(&uri);
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let history = call!(
+            app,
+            test::TestRequest::get().uri(&format!("{}/history", uri))
+        );
+        let revisions: Vec<(i64, &str, &str)> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["revision"].as_i64().unwrap(),
+                    r["content"].as_str().unwrap(),
+                    r["reason"].as_str().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            revisions,
+            vec![
+                (1, view, ""),
+                (2, edit, "edit only"),
+                (3, view, "rollback to revision 1"),
+                (4, edit, "rollback to revision 2"),
+                (5, edit, "deleted"),
+            ]
+        );
+        assert_eq!(history[0]["author"], "ann");
+
+        let req = test::TestRequest::post().uri(&format!("{}/rollback/1", uri));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
 }
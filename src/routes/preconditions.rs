@@ -0,0 +1,146 @@
+use crate::routes::api_error::ApiError;
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, ETag, EntityTag, Header, IfMatch};
+use actix_web::{FromRequest, HttpRequest};
+use futures_util::future::{ready, Ready};
+use sqlx::{Sqlite, Transaction};
+
+/// The `ETag` of a row at the given version.
+pub fn etag(version: i64) -> ETag {
+    ETag(EntityTag::new_strong(version.to_string()))
+}
+
+/// The `If-Match` header of a write. Unlike `web::Header<IfMatch>`, which reads a missing or
+/// unparsable header as an empty list of tags, a header without a single valid tag is
+/// rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// No `If-Match` header
+    Absent,
+    /// `If-Match: *`
+    Any,
+    /// The versions listed by strong tags. Weak or non-numeric tags never match a version,
+    /// so this may be empty.
+    Versions(Vec<i64>),
+}
+
+impl Precondition {
+    fn parse(req: &HttpRequest) -> Result<Self, ApiError> {
+        if !req.headers().contains_key(header::IF_MATCH) {
+            return Ok(Precondition::Absent);
+        }
+
+        match IfMatch::parse(req) {
+            Ok(IfMatch::Any) => Ok(Precondition::Any),
+            Ok(IfMatch::Items(tags)) if !tags.is_empty() => Ok(Precondition::Versions(
+                tags.iter()
+                    .filter(|tag| !tag.weak)
+                    .filter_map(|tag| tag.tag().parse().ok())
+                    .collect(),
+            )),
+            _ => Err(ApiError::PreconditionFailed(
+                "If-Match must be `*` or a list of quoted entity tags".to_string(),
+            )),
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        *self != Precondition::Absent
+    }
+
+    /// Checks the current version of a row. A missing header always matches.
+    pub fn check(&self, version: i64) -> Result<(), ApiError> {
+        match self {
+            Precondition::Absent | Precondition::Any => Ok(()),
+            Precondition::Versions(versions) if versions.contains(&version) => Ok(()),
+            Precondition::Versions(_) => Err(ApiError::PreconditionFailed(format!(
+                "If-Match does not match the current version \"{}\"",
+                version
+            ))),
+        }
+    }
+
+    /// Checks the current version of a row and returns the version a conditional write has
+    /// to find. Without `If-Match` the write applies to whatever version the row has by then.
+    pub fn guard(&self, version: i64) -> Result<Option<i64>, ApiError> {
+        self.check(version)?;
+        Ok(self.is_present().then_some(version))
+    }
+}
+
+impl FromRequest for Precondition {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Precondition::parse(req))
+    }
+}
+
+/// The error for a write guarded by `Precondition::guard` that changed no row: the row was
+/// deleted or, for a conditional write, written again since its version was checked.
+pub async fn unmatched(tr: &mut Transaction<'_, Sqlite>, table: &str, id: &str) -> ApiError {
+    let query = format!("SELECT version FROM {} WHERE id = $1", table);
+    match sqlx::query_scalar::<_, i64>(&query)
+        .bind(id)
+        .fetch_optional(&mut *tr)
+        .await
+    {
+        Ok(Some(version)) => ApiError::PreconditionFailed(format!(
+            "{} was modified concurrently, its current version is \"{}\"",
+            id, version
+        )),
+        Ok(None) => ApiError::NotFound(format!("{} not found", id)),
+        Err(err) => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Precondition;
+    use crate::routes::api_error::ApiError;
+    use actix_web::http::header::IF_MATCH;
+    use actix_web::test::TestRequest;
+
+    fn parse(if_match: Option<&str>) -> Result<Precondition, ApiError> {
+        let mut req = TestRequest::default();
+        if let Some(value) = if_match {
+            req = req.insert_header((IF_MATCH, value));
+        }
+        Precondition::parse(&req.to_http_request())
+    }
+
+    #[test]
+    fn precondition_should_parse_if_match() {
+        assert_eq!(parse(None).unwrap(), Precondition::Absent);
+        assert_eq!(parse(Some("*")).unwrap(), Precondition::Any);
+        assert_eq!(
+            parse(Some(r#""3", W/"4", "x""#)).unwrap(),
+            Precondition::Versions(vec![3])
+        );
+        assert!(matches!(
+            parse(Some("3")),
+            Err(ApiError::PreconditionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn precondition_should_compare_versions() {
+        assert!(Precondition::Absent.check(3).is_ok());
+        assert!(Precondition::Any.check(3).is_ok());
+        assert!(Precondition::Versions(vec![2, 3]).check(3).is_ok());
+        assert!(matches!(
+            Precondition::Versions(vec![2]).check(3),
+            Err(ApiError::PreconditionFailed(_))
+        ));
+        assert!(Precondition::Versions(vec![]).check(3).is_err());
+    }
+
+    #[test]
+    fn precondition_should_only_guard_conditional_writes() {
+        assert_eq!(Precondition::Absent.guard(3).unwrap(), None);
+        assert_eq!(Precondition::Any.guard(3).unwrap(), Some(3));
+        assert_eq!(Precondition::Versions(vec![3]).guard(3).unwrap(), Some(3));
+        assert!(Precondition::Versions(vec![2]).guard(3).is_err());
+    }
+}
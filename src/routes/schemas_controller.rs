@@ -0,0 +1,194 @@
+use crate::dto::schemas::{Schema, SchemaInput};
+use crate::routes::api_error::ApiError;
+use crate::routes::api_response::ApiResponse;
+use crate::routes::app_state::AppState;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use sqlx::SqlitePool;
+
+fn validate_schema(schema_input: &SchemaInput) -> Result<(), ApiError> {
+    match cedar_policy::Schema::from_json_value(schema_input.content.clone()) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ApiError::Validation(err.to_string())),
+    }
+}
+
+#[post("")]
+pub async fn add(
+    app_state: web::Data<AppState>,
+    schema_input: web::Json<SchemaInput>,
+) -> Result<HttpResponse, ApiError> {
+    validate_schema(&schema_input)?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let insert_query = "INSERT INTO schemas (id, content) VALUES($1,$2) RETURNING id";
+
+    let row: (String,) = sqlx::query_as(insert_query)
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(&schema_input.content)
+        .fetch_one(&mut tr)
+        .await?;
+
+    let id = row.0;
+    tr.commit().await?;
+    app_state.schema.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(id),
+        pagination: None,
+    }))
+}
+
+#[get("")]
+pub async fn get_all(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    match get_all_schemas(&app_state.pool).await {
+        Ok(schemas) => {
+            let schemas_value: serde_json::Value = serde_json::to_value(schemas)?;
+
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                status_code: "200".to_string(),
+                message: "Successful".to_string(),
+                data: schemas_value,
+                pagination: None,
+            }))
+        }
+        Err(e) => Err(ApiError::NotFound(e.to_string())),
+    }
+}
+
+async fn get_all_schemas(pool: &SqlitePool) -> Result<Vec<Schema>, sqlx::Error> {
+    let schemas =
+        sqlx::query_as::<sqlx::Sqlite, Schema>("SELECT id,content,active,created_ts FROM schemas")
+            .fetch_all(pool)
+            .await?;
+    Ok(schemas)
+}
+
+#[put("/{id}")]
+pub async fn update(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    schema_input: web::Json<SchemaInput>,
+) -> Result<HttpResponse, ApiError> {
+    let schema_id = path.into_inner();
+
+    validate_schema(&schema_input)?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let query = "UPDATE schemas SET content = $1 WHERE id = $2 RETURNING id";
+    let row: (String,) = sqlx::query_as(query)
+        .bind(&schema_input.content)
+        .bind(schema_id)
+        .fetch_one(&mut tr)
+        .await?;
+    let updated_id = row.0;
+    tr.commit().await?;
+    app_state.schema.refresh(&app_state.pool).await;
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(updated_id),
+        pagination: None,
+    }))
+}
+
+#[post("/{id}/activate")]
+pub async fn activate(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let schema_id = path.into_inner();
+    let mut tr = app_state.pool.begin().await?;
+
+    sqlx::query("UPDATE schemas SET active = 0 WHERE active = 1")
+        .execute(&mut tr)
+        .await?;
+
+    let row: (String,) = sqlx::query_as("UPDATE schemas SET active = 1 WHERE id = $1 RETURNING id")
+        .bind(schema_id)
+        .fetch_one(&mut tr)
+        .await?;
+    let activated_id = row.0;
+    tr.commit().await?;
+    app_state.schema.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(activated_id),
+        pagination: None,
+    }))
+}
+
+#[delete("/{id}")]
+pub async fn remove(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let schema_id = path.into_inner();
+    let mut tr = app_state.pool.begin().await?;
+    let query = "
+    DELETE FROM schemas
+    WHERE id = $1 RETURNING id;
+";
+
+    let row: (String,) = sqlx::query_as(query)
+        .bind(schema_id)
+        .fetch_one(&mut tr)
+        .await?;
+    let deleted_id = row.0;
+    tr.commit().await?;
+    app_state.schema.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(deleted_id),
+        pagination: None,
+    }))
+}
+
+#[get("/{id}")]
+pub async fn get_by_id(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let schema_id = path.into_inner();
+
+    match get_schema_by_id(&app_state.pool, schema_id).await {
+        Ok(schema) => {
+            let schema_value: serde_json::Value = serde_json::to_value(schema)?;
+
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                status_code: "200".to_string(),
+                message: "Successful".to_string(),
+                data: schema_value,
+                pagination: None,
+            }))
+        }
+        Err(e) => Err(ApiError::NotFound(e.to_string())),
+    }
+}
+
+async fn get_schema_by_id(pool: &SqlitePool, id: String) -> Result<Schema, sqlx::Error> {
+    let schema = sqlx::query_as::<sqlx::Sqlite, Schema>(
+        "SELECT id,content,active,created_ts FROM schemas WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(schema)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(add)
+        .service(update)
+        .service(activate)
+        .service(remove)
+        .service(get_all)
+        .service(get_by_id);
+}
@@ -0,0 +1,334 @@
+use crate::cedar::api::{link_template, parse_template, validate_policies};
+use crate::dto::templates::{LinkInput, Template, TemplateInput, TemplateLink};
+use crate::routes::api_error::ApiError;
+use crate::routes::api_response::ApiResponse;
+use crate::routes::app_state::AppState;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use cedar_policy::PolicySet;
+use chrono::Utc;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// Builds a policy set holding the template and the given links, checked against the active
+/// schema if there is one.
+fn validate_template(
+    app_state: &AppState,
+    id: &str,
+    content: &str,
+    links: &[TemplateLink],
+) -> Result<(), ApiError> {
+    let template = parse_template(id, content).map_err(ApiError::Validation)?;
+
+    let mut policies = PolicySet::new();
+    policies
+        .add_template(template)
+        .map_err(anyhow::Error::from)?;
+    for link in links {
+        link_template(
+            &mut policies,
+            id,
+            &link.id,
+            link.principal.as_deref(),
+            link.resource.as_deref(),
+        )
+        .map_err(|err| ApiError::Validation(format!("link {}: {}", link.id, err)))?;
+    }
+
+    if let Some(schema) = app_state.schema.get() {
+        validate_policies(&policies, &schema.schema)
+            .map_err(|errors| ApiError::Validation(errors.join("\n")))?;
+    }
+
+    Ok(())
+}
+
+#[post("")]
+pub async fn add(
+    app_state: web::Data<AppState>,
+    template_input: web::Json<TemplateInput>,
+) -> Result<HttpResponse, ApiError> {
+    template_input.validate()?;
+
+    let template_id = uuid::Uuid::new_v4().to_string();
+
+    validate_template(&app_state, &template_id, &template_input.content, &[])?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let current_time = Utc::now();
+
+    let insert_query = "INSERT INTO templates
+        (id, content, created_ts, updated_ts)
+         VALUES($1,$2,$3,$4) RETURNING id";
+
+    let row: (String,) = sqlx::query_as(insert_query)
+        .bind(template_id)
+        .bind(&template_input.content)
+        .bind(current_time.to_rfc3339())
+        .bind("".to_string())
+        .fetch_one(&mut tr)
+        .await?;
+
+    let id = row.0;
+    tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(id),
+        pagination: None,
+    }))
+}
+
+#[get("")]
+pub async fn get_all(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    match get_all_templates(&app_state.pool).await {
+        Ok(templates) => {
+            let templates_value: serde_json::Value = serde_json::to_value(templates)?;
+
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                status_code: "200".to_string(),
+                message: "Successful".to_string(),
+                data: templates_value,
+                pagination: None,
+            }))
+        }
+        Err(e) => Err(ApiError::NotFound(e.to_string())),
+    }
+}
+
+async fn get_all_templates(pool: &SqlitePool) -> Result<Vec<Template>, sqlx::Error> {
+    let templates = sqlx::query_as::<sqlx::Sqlite, Template>(
+        "SELECT id,content,created_ts,updated_ts FROM templates",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(templates)
+}
+
+#[put("/{id}")]
+pub async fn update(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    template_input: web::Json<TemplateInput>,
+) -> Result<HttpResponse, ApiError> {
+    let template_id = path.into_inner();
+
+    template_input.validate()?;
+
+    // Every existing link has to keep working with the new slots
+    let links = get_links_by_template_id(&app_state.pool, template_id.clone()).await?;
+    validate_template(&app_state, &template_id, &template_input.content, &links)?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let current_time = Utc::now();
+
+    let query = "UPDATE templates SET content = $1, updated_ts = $2 WHERE id = $3 RETURNING id";
+    let row: (String,) = sqlx::query_as(query)
+        .bind(&template_input.content)
+        .bind(current_time.to_rfc3339())
+        .bind(template_id)
+        .fetch_one(&mut tr)
+        .await?;
+    let updated_id = row.0;
+    tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(updated_id),
+        pagination: None,
+    }))
+}
+
+#[delete("/{id}")]
+pub async fn remove(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let template_id = path.into_inner();
+    let mut tr = app_state.pool.begin().await?;
+    let query = "
+    DELETE FROM templates
+    WHERE id = $1 RETURNING id;
+";
+
+    let row: (String,) = sqlx::query_as(query)
+        .bind(template_id)
+        .fetch_one(&mut tr)
+        .await?;
+    let deleted_id = row.0;
+    tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(deleted_id),
+        pagination: None,
+    }))
+}
+
+#[get("/{id}")]
+pub async fn get_by_id(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let template_id = path.into_inner();
+
+    match get_template_by_id(&app_state.pool, template_id).await {
+        Ok(template) => {
+            let template_value: serde_json::Value = serde_json::to_value(template)?;
+
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                status_code: "200".to_string(),
+                message: "Successful".to_string(),
+                data: template_value,
+                pagination: None,
+            }))
+        }
+        Err(e) => Err(ApiError::NotFound(e.to_string())),
+    }
+}
+
+async fn get_template_by_id(pool: &SqlitePool, id: String) -> Result<Template, sqlx::Error> {
+    let template = sqlx::query_as::<sqlx::Sqlite, Template>(
+        "SELECT id,content,created_ts,updated_ts FROM templates WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(template)
+}
+
+#[post("/{id}/links")]
+pub async fn add_link(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    link_input: web::Json<LinkInput>,
+) -> Result<HttpResponse, ApiError> {
+    let template_id = path.into_inner();
+    let template = get_template_by_id(&app_state.pool, template_id.clone()).await?;
+
+    let current_time = Utc::now();
+
+    let link = TemplateLink {
+        id: uuid::Uuid::new_v4().to_string(),
+        template_id,
+        principal: link_input.principal.clone(),
+        resource: link_input.resource.clone(),
+        created_ts: current_time.to_rfc3339(),
+    };
+    validate_template(
+        &app_state,
+        &template.id,
+        &template.content,
+        std::slice::from_ref(&link),
+    )?;
+
+    let mut tr = app_state.pool.begin().await?;
+
+    let insert_query = "INSERT INTO template_links
+        (id, template_id, principal, resource, created_ts)
+         VALUES($1,$2,$3,$4,$5) RETURNING id";
+
+    let row: (String,) = sqlx::query_as(insert_query)
+        .bind(link.id)
+        .bind(link.template_id)
+        .bind(link.principal)
+        .bind(link.resource)
+        .bind(link.created_ts)
+        .fetch_one(&mut tr)
+        .await?;
+
+    let id = row.0;
+    tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(id),
+        pagination: None,
+    }))
+}
+
+#[get("/{id}/links")]
+pub async fn get_links(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let template_id = path.into_inner();
+
+    match get_links_by_template_id(&app_state.pool, template_id).await {
+        Ok(links) => {
+            let links_value: serde_json::Value = serde_json::to_value(links)?;
+
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                status_code: "200".to_string(),
+                message: "Successful".to_string(),
+                data: links_value,
+                pagination: None,
+            }))
+        }
+        Err(e) => Err(ApiError::NotFound(e.to_string())),
+    }
+}
+
+async fn get_links_by_template_id(
+    pool: &SqlitePool,
+    template_id: String,
+) -> Result<Vec<TemplateLink>, sqlx::Error> {
+    let links = sqlx::query_as::<sqlx::Sqlite, TemplateLink>(
+        "SELECT id,template_id,principal,resource,created_ts FROM template_links
+         WHERE template_id = ?",
+    )
+    .bind(template_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(links)
+}
+
+#[delete("/{id}/links/{link_id}")]
+pub async fn remove_link(
+    path: web::Path<(String, String)>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let (template_id, link_id) = path.into_inner();
+    let mut tr = app_state.pool.begin().await?;
+    let query = "
+    DELETE FROM template_links
+    WHERE id = $1 AND template_id = $2 RETURNING id;
+";
+
+    let row: (String,) = sqlx::query_as(query)
+        .bind(link_id)
+        .bind(template_id)
+        .fetch_one(&mut tr)
+        .await?;
+    let deleted_id = row.0;
+    tr.commit().await?;
+    app_state.policies.refresh(&app_state.pool).await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        status_code: "200".to_string(),
+        message: "Successful".to_string(),
+        data: serde_json::Value::String(deleted_id),
+        pagination: None,
+    }))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(add)
+        .service(update)
+        .service(remove)
+        .service(get_all)
+        .service(get_by_id)
+        .service(add_link)
+        .service(get_links)
+        .service(remove_link);
+}
@@ -1,19 +1,25 @@
-use crate::routes::app_state::AppState;
-use crate::routes::{entities_config, policies_config};
 use actix_cors::Cors;
 use actix_web::{http::header, middleware, middleware::Logger, web, App, HttpServer};
-use cedar_authorizer::http::authz::authorize;
+use cedar_authorizer::cedar::api::split_legacy_policies;
+use cedar_authorizer::cedar::cache::{EntityCache, PolicyCache, SchemaCache};
+use cedar_authorizer::http::authz::{authorize, authorize_batch};
 use cedar_authorizer::routes::api_error::ApiError;
+use cedar_authorizer::routes::app_state::AppState;
 use cedar_authorizer::routes::health_check;
-use cedar_authorizer::utils::env_helper::AppEnv;
+use cedar_authorizer::routes::{
+    entities_config, policies_config, schemas_config, templates_config,
+};
+use cedar_authorizer::utils::env_helper::{AppEnv, IntegrityConfig};
 use dotenv::var;
 use sqlx::migrate::MigrateDatabase;
 use sqlx::migrate::Migrator;
 use sqlx::Sqlite;
 use std::path::Path;
+use std::sync::Arc;
 
 pub async fn server() -> Result<(), ApiError> {
     let app_environment = AppEnv::current_env()?;
+    let integrity = IntegrityConfig::current()?;
     let dev_db = var("DB_FILE").expect("DB name must be set");
     let url = var("URL").expect("url must be set in env");
     let db_conn = format!("sqlite://{}", &dev_db);
@@ -37,12 +43,29 @@ pub async fn server() -> Result<(), ApiError> {
         .run(&pool)
         .await
         .expect("Failed to migrate the database");
-    let app_state = AppState { pool };
+    split_legacy_policies(&pool)
+        .await
+        .expect("Failed to split multi-statement policies");
+    let policies = PolicyCache::load(&pool)
+        .await
+        .expect("Failed to load the stored policies");
+    let schema = SchemaCache::load(&pool)
+        .await
+        .expect("Failed to load the active schema");
+    let app_state = AppState {
+        pool,
+        policies: Arc::new(policies),
+        schema: Arc::new(schema),
+        entities: Arc::new(EntityCache::default()),
+        integrity,
+    };
     let server = HttpServer::new(move || {
         let cors_base = Cors::default()
-            .allowed_methods(vec!["POST", "GET"])
+            .allowed_methods(vec!["POST", "GET", "PUT", "PATCH", "DELETE"])
             .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
             .allowed_header(header::CONTENT_TYPE)
+            .allowed_header(header::IF_MATCH)
+            .expose_headers(vec![header::ETAG])
             .supports_credentials()
             .max_age(3600);
 
@@ -66,7 +89,10 @@ pub async fn server() -> Result<(), ApiError> {
                 web::scope("/api")
                     .service(web::scope("/entities").configure(entities_config))
                     .service(web::scope("/policies").configure(policies_config))
-                    .route("/authorize", web::post().to(authorize)),
+                    .service(web::scope("/schemas").configure(schemas_config))
+                    .service(web::scope("/templates").configure(templates_config))
+                    .route("/authorize", web::post().to(authorize))
+                    .route("/authorize/batch", web::post().to(authorize_batch)),
             )
             .route("/health_check", web::get().to(health_check))
     });
@@ -7,11 +7,10 @@ static POOL: OnceCell<Pool<Sqlite>> = OnceCell::new();
 #[inline]
 pub async fn get_conn() -> PoolConnection<Sqlite> {
     unsafe {
-        return POOL
-            .get_unchecked()
+        POOL.get_unchecked()
             .acquire()
             .await
-            .expect("Unable to acquire DB connection");
+            .expect("Unable to acquire DB connection")
     }
 }
 
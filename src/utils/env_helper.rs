@@ -25,6 +25,51 @@ impl AppEnv {
     }
 }
 
+/// What happens to the child entities and policies that reference an entity being deleted
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum EntityDeleteMode {
+    /// Delete without looking for references
+    #[default]
+    Allow,
+    /// Refuse to delete a referenced entity
+    Block,
+    /// Remove the entity from the parents of its children and report the referencing policies
+    Cascade,
+    /// Delete and report the references left behind
+    Report,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IntegrityConfig {
+    /// Reject entities whose parents are not stored
+    pub check_parents: bool,
+    pub on_entity_delete: EntityDeleteMode,
+}
+
+impl IntegrityConfig {
+    /// Reads the optional `ENTITY_PARENT_CHECK` (`on` or `off`) and `ENTITY_DELETE_MODE`
+    /// (`allow`, `block`, `cascade` or `report`) settings.
+    pub fn current() -> Result<IntegrityConfig> {
+        let check_parents = match var("ENTITY_PARENT_CHECK").as_deref() {
+            Err(_) | Ok("off") => false,
+            Ok("on") => true,
+            Ok(other) => bail!("Invalid ENTITY_PARENT_CHECK `{}`", other),
+        };
+        let on_entity_delete = match var("ENTITY_DELETE_MODE").as_deref() {
+            Err(_) | Ok("allow") => EntityDeleteMode::Allow,
+            Ok("block") => EntityDeleteMode::Block,
+            Ok("cascade") => EntityDeleteMode::Cascade,
+            Ok("report") => EntityDeleteMode::Report,
+            Ok(other) => bail!("Invalid ENTITY_DELETE_MODE `{}`", other),
+        };
+
+        Ok(IntegrityConfig {
+            check_parents,
+            on_entity_delete,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::env_helper::AppEnv;
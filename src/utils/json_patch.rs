@@ -0,0 +1,185 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// One operation of a JSON Patch (RFC 6902) document
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Applies a JSON Merge Patch (RFC 7396) to `target`.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(key);
+            } else {
+                merge_patch(map.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Applies a JSON Patch (RFC 6902) to `target`. Either every operation is applied or, on
+/// error, `target` is left untouched.
+pub fn apply_patch(target: &mut Value, patch: &Value) -> Result<(), String> {
+    let operations: Vec<PatchOperation> =
+        serde_json::from_value(patch.clone()).map_err(|err| err.to_string())?;
+
+    let mut patched = target.clone();
+    for (index, operation) in operations.into_iter().enumerate() {
+        apply_operation(&mut patched, operation)
+            .map_err(|err| format!("operation {}: {}", index, err))?;
+    }
+    *target = patched;
+    Ok(())
+}
+
+fn apply_operation(target: &mut Value, operation: PatchOperation) -> Result<(), String> {
+    match operation {
+        PatchOperation::Add { path, value } => add(target, &path, value),
+        PatchOperation::Remove { path } => remove(target, &path).map(|_| ()),
+        PatchOperation::Replace { path, value } => match target.pointer_mut(&path) {
+            Some(current) => {
+                *current = value;
+                Ok(())
+            }
+            None => Err(format!("path `{}` does not exist", path)),
+        },
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("cannot move `{}` into itself", from));
+            }
+            let value = remove(target, &from)?;
+            add(target, &path, value)
+        }
+        PatchOperation::Copy { from, path } => match target.pointer(&from).cloned() {
+            Some(value) => add(target, &path, value),
+            None => Err(format!("path `{}` does not exist", from)),
+        },
+        PatchOperation::Test { path, value } => match target.pointer(&path) {
+            Some(current) if *current == value => Ok(()),
+            _ => Err(format!("test failed for path `{}`", path)),
+        },
+    }
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+
+    let (parent, key) = split_pointer(path)?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(key, value);
+            Ok(())
+        }
+        Some(Value::Array(items)) => {
+            let index = match key.as_str() {
+                "-" => items.len(),
+                _ => array_index(&key, items.len() + 1)?,
+            };
+            items.insert(index, value);
+            Ok(())
+        }
+        _ => Err(format!("path `{}` does not exist", path)),
+    }
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent, key) = split_pointer(path)?;
+    let removed = match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&key),
+        Some(Value::Array(items)) => {
+            let index = array_index(&key, items.len())?;
+            Some(items.remove(index))
+        }
+        _ => None,
+    };
+    removed.ok_or_else(|| format!("path `{}` does not exist", path))
+}
+
+/// Splits a JSON pointer into the pointer of its parent and its unescaped last token.
+fn split_pointer(path: &str) -> Result<(&str, String), String> {
+    match path.rsplit_once('/') {
+        Some((parent, key)) if path.starts_with('/') => {
+            Ok((parent, key.replace("~1", "/").replace("~0", "~")))
+        }
+        _ => Err(format!("invalid JSON pointer `{}`", path)),
+    }
+}
+
+fn array_index(token: &str, len: usize) -> Result<usize, String> {
+    let index = match token.parse::<usize>() {
+        Ok(index) if token == "0" || !token.starts_with('0') => index,
+        _ => return Err(format!("invalid array index `{}`", token)),
+    };
+    if index < len {
+        Ok(index)
+    } else {
+        Err(format!("array index {} is out of bounds", index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_patch, merge_patch};
+    use serde_json::json;
+
+    #[test]
+    fn merge_patch_should_replace_and_remove_members() {
+        let mut target = json!({"attrs": {"age": 30, "name": "alice"}, "parents": []});
+        merge_patch(
+            &mut target,
+            &json!({"attrs": {"age": 31, "name": null}, "parents": [{"type": "Group", "id": "a"}]}),
+        );
+        assert_eq!(
+            target,
+            json!({"attrs": {"age": 31}, "parents": [{"type": "Group", "id": "a"}]})
+        );
+    }
+
+    #[test]
+    fn apply_patch_should_be_all_or_nothing() {
+        let mut target = json!({"attrs": {"age": 30}, "parents": [{"type": "Group", "id": "a"}]});
+        apply_patch(
+            &mut target,
+            &json!([
+                {"op": "add", "path": "/parents/-", "value": {"type": "Group", "id": "b"}},
+                {"op": "replace", "path": "/attrs/age", "value": 31},
+                {"op": "remove", "path": "/parents/0"},
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            target,
+            json!({"attrs": {"age": 31}, "parents": [{"type": "Group", "id": "b"}]})
+        );
+
+        let failed = apply_patch(
+            &mut target,
+            &json!([
+                {"op": "remove", "path": "/attrs/age"},
+                {"op": "test", "path": "/attrs/age", "value": 31},
+            ]),
+        );
+        assert!(failed.is_err());
+        assert_eq!(target["attrs"]["age"], 31);
+    }
+}
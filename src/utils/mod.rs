@@ -1,2 +1,5 @@
 pub mod dbpool;
 pub mod env_helper;
+pub mod json_patch;
+#[cfg(test)]
+pub mod test_utils;
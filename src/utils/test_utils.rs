@@ -0,0 +1,74 @@
+use crate::cedar::cache::{EntityCache, PolicyCache, SchemaCache};
+use crate::routes::app_state::AppState;
+use crate::utils::env_helper::IntegrityConfig;
+use serde_json::Value;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// A migrated in-memory database. It only lives as long as its one connection.
+pub async fn memory_pool() -> SqlitePool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    pool
+}
+
+/// Handlers read outside their write transactions, so the pool needs more than the one
+/// connection an in-memory database allows.
+pub async fn app_state(integrity: IntegrityConfig) -> (AppState, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let options = SqliteConnectOptions::new()
+        .filename(dir.path().join("test.db"))
+        .create_if_missing(true);
+    let pool = SqlitePoolOptions::new()
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+
+    let app_state = AppState {
+        policies: Arc::new(PolicyCache::load(&pool).await.unwrap()),
+        schema: Arc::new(SchemaCache::load(&pool).await.unwrap()),
+        entities: Arc::new(EntityCache::default()),
+        integrity,
+        pool,
+    };
+    (app_state, dir)
+}
+
+/// Inserts a Cedar entity JSON object along with its `entity_parents` edges.
+pub async fn insert_entity(pool: &SqlitePool, content: Value) {
+    let id = uuid::Uuid::new_v4().to_string();
+    sqlx::query("INSERT INTO entities (id, eid, etype, content) VALUES ($1, $2, $3, $4)")
+        .bind(&id)
+        .bind(content["uid"]["id"].as_str())
+        .bind(content["uid"]["type"].as_str())
+        .bind(&content)
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO entity_parents (entity_id, parent_etype, parent_eid)
+         SELECT e.id, json_extract(p.value, '$.type'), json_extract(p.value, '$.id')
+         FROM entities e, json_each(e.content, '$.parents') p WHERE e.id = $1",
+    )
+    .bind(&id)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+/// Calls a test service and returns the `data` of its `ApiResponse`.
+macro_rules! call {
+    ($app:expr, $req:expr) => {{
+        let mut res: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&$app, $req.to_request()).await;
+        res["data"].take()
+    }};
+}
+pub(crate) use call;